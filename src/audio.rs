@@ -65,6 +65,49 @@ where
     Ok(stream)
 }
 
+/// Biquad low-pass filter over interleaved stereo samples, used to remove
+/// frequencies the output device can't reproduce before downsampling.
+pub struct LowPassFilter {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    /// Last two inputs and outputs, per channel.
+    history: [[f32; 4]; 2],
+}
+
+impl LowPassFilter {
+    pub fn new(cutoff: f64, sample_rate: f64) -> Self {
+        let w0 = 2.0 * std::f64::consts::PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * std::f64::consts::FRAC_1_SQRT_2);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: ((1.0 - cos) / 2.0 / a0) as f32,
+            b1: ((1.0 - cos) / a0) as f32,
+            b2: ((1.0 - cos) / 2.0 / a0) as f32,
+            a1: (-2.0 * cos / a0) as f32,
+            a2: ((1.0 - alpha) / a0) as f32,
+            history: [[0.0; 4]; 2],
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [i16]) {
+        for frame in samples.chunks_exact_mut(2) {
+            for (sample, history) in frame.iter_mut().zip(self.history.iter_mut()) {
+                let [x1, x2, y1, y2] = *history;
+                let x = *sample as f32;
+                let y = self.b0 * x + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+
+                *history = [x, x1, y, y1];
+                *sample = y.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+    }
+}
+
 /*fn write_data<T, F>(
     output: &mut [T],
     channels: usize,
@@ -1,7 +1,22 @@
-use std::path::Path;
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::hash::{bytes_to_hex, RomHashError, Sha1Hash};
+use crate::hash::{bytes_to_hex, RomHashError};
 
+/// Bump whenever hashing changes in a way that makes old hashes wrong, so
+/// stale entries don't keep misidentifying ROMs.
+const HASH_CACHE_VERSION: u32 = 4;
+/// Bump whenever the way images are stored changes.
+const IMAGE_CACHE_VERSION: u32 = 2;
+
+/// Key the version is stored under. Can't clash with a path or URL.
+const VERSION_KEY: &[u8] = b"\0version";
+
+/// ROM hash and cover image caches. sled handles locking internally, so a
+/// shared reference can be used from any thread.
+#[derive(Clone)]
 pub struct Cache {
     hash_cache: sled::Db,
     image_cache: sled::Db,
@@ -13,14 +28,14 @@ impl Cache {
         P: AsRef<Path>,
     {
         Ok(Self {
-            hash_cache: sled::open(hash_cache_path)?,
-            image_cache: sled::open(image_cache_path)?,
+            hash_cache: open_versioned(hash_cache_path, HASH_CACHE_VERSION)?,
+            image_cache: open_versioned(image_cache_path, IMAGE_CACHE_VERSION)?,
         })
     }
 
-    pub fn get_or_insert_rom_hash<F>(&mut self, path: &str, mut f: F) -> anyhow::Result<String>
+    pub fn get_or_insert_rom_hash<F>(&self, path: &str, mut f: F) -> anyhow::Result<String>
     where
-        F: FnMut(&str) -> Result<Sha1Hash, RomHashError>,
+        F: FnMut(&str) -> Result<Vec<u8>, RomHashError>,
     {
         if let Some(hash) = self.hash_cache.get(path)? {
             Ok(String::from_utf8(hash.to_vec())?)
@@ -31,18 +46,83 @@ impl Cache {
         }
     }
 
-    pub fn get_or_insert_image<F>(&mut self, url: &str, mut f: F) -> anyhow::Result<Vec<u8>>
+    pub fn remove_rom_hash(&self, path: &str) -> anyhow::Result<()> {
+        self.hash_cache.remove(path)?;
+        Ok(())
+    }
+
+    /// Images older than `max_age` are fetched again. If that fails, the old
+    /// image is used anyway.
+    pub fn get_or_insert_image<F>(
+        &self,
+        url: &str,
+        max_age: Option<Duration>,
+        mut f: F,
+    ) -> anyhow::Result<Vec<u8>>
     where
         F: FnMut(&str) -> Result<Vec<u8>, anyhow::Error>,
     {
-        let bytes = if let Some(bytes) = self.image_cache.get(url)? {
-            bytes.to_vec()
-        } else {
-            let bytes = f(url)?;
-            self.image_cache.insert(url, &bytes[..])?;
-            bytes
-        };
+        // Entries are the insertion time in seconds since the epoch, followed
+        // by the image
+        let cached = self.image_cache.get(url)?.and_then(|entry| {
+            if entry.len() < 8 {
+                return None;
+            }
+
+            let (timestamp, bytes) = entry.split_at(8);
+            let seconds = u64::from_le_bytes(timestamp.try_into().ok()?);
+            Some((UNIX_EPOCH + Duration::from_secs(seconds), bytes.to_vec()))
+        });
 
-        Ok(bytes)
+        if let Some((inserted, bytes)) = &cached {
+            let expired = max_age.map_or(false, |max_age| {
+                inserted.elapsed().map_or(true, |age| age > max_age)
+            });
+
+            if !expired {
+                return Ok(bytes.clone());
+            }
+        }
+
+        match f(url) {
+            Ok(bytes) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let mut entry = now.to_le_bytes().to_vec();
+                entry.extend_from_slice(&bytes);
+                self.image_cache.insert(url, entry)?;
+                Ok(bytes)
+            }
+            Err(e) => match cached {
+                Some((_, bytes)) => {
+                    log::warn!("Couldn't refresh cached image {}: {:#}", url, e);
+                    Ok(bytes)
+                }
+                None => Err(e),
+            },
+        }
     }
 }
+
+/// Opens a cache, clearing it if it was written by a different cache version.
+fn open_versioned<P>(path: P, version: u32) -> Result<sled::Db, sled::Error>
+where
+    P: AsRef<Path>,
+{
+    let db = sled::open(&path)?;
+    let version_bytes = version.to_le_bytes();
+
+    if db.get(VERSION_KEY)?.as_deref() != Some(&version_bytes[..]) {
+        log::info!(
+            "Cache {:?} is outdated, clearing it (version {})",
+            path.as_ref(),
+            version
+        );
+        db.clear()?;
+        db.insert(VERSION_KEY, &version_bytes[..])?;
+    }
+
+    Ok(db)
+}
@@ -1,25 +1,349 @@
 use std::{
-    fs,
+    collections::HashMap,
+    env, fs,
     path::{Path, PathBuf},
 };
 
-use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::{bail, Context, Result};
+use gilrs::Button;
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+use crate::hash::HashAlgorithm;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Config {
-    pub rom_path: PathBuf,
+    /// Directories scanned for ROMs. A single path is accepted too.
+    #[serde(deserialize_with = "one_or_many_paths")]
+    pub rom_path: Vec<PathBuf>,
+    /// Scan into symlinked directories under `rom_path`.
+    #[serde(default)]
+    pub follow_symlinks: bool,
     pub core_path: PathBuf,
     pub cache_path: PathBuf,
+    /// The OpenVGDB SQLite database used to identify ROMs.
+    #[serde(default = "default_openvgdb_path")]
+    pub openvgdb_path: PathBuf,
+    /// Where battery-backed save RAM (`.srm`) files are kept.
+    #[serde(default = "default_save_path")]
+    pub save_path: PathBuf,
+    /// Custom covers (`<hash>.png`), shown instead of the scraped ones.
+    #[serde(default = "default_cover_path")]
+    pub cover_path: PathBuf,
+    /// Days before a downloaded cover is fetched again. Zero keeps them
+    /// forever.
+    #[serde(default = "default_cover_max_age_days")]
+    pub cover_max_age_days: u64,
+    /// Download every missing cover in the background at startup, instead
+    /// of as games scroll into view.
+    #[serde(default)]
+    pub prefetch_covers: bool,
+    /// Directory meant for BIOS/firmware files (PSX, Saturn...). Not handed
+    /// to cores yet: retro-rs answers `RETRO_ENVIRONMENT_GET_SYSTEM_DIRECTORY`
+    /// itself and has no way to override it.
+    #[serde(default)]
+    pub system_path: Option<PathBuf>,
+    /// Whether the window starts in fullscreen mode. Can be toggled at
+    /// runtime with F11.
+    #[serde(default)]
+    pub fullscreen: bool,
+    /// Initial window width in pixels.
+    #[serde(default = "default_window_width")]
+    pub window_width: i32,
+    /// Initial window height in pixels.
+    #[serde(default = "default_window_height")]
+    pub window_height: i32,
+    /// Wait for the display's vertical blank before presenting each frame.
+    #[serde(default = "default_vsync")]
+    pub vsync: bool,
+    /// Frames per second the app is held to when `vsync` is off. Zero
+    /// doesn't limit it.
+    #[serde(default)]
+    pub max_fps: u32,
+    /// RetroArch playlists (`.lpl`) whose games are added as they are,
+    /// without scanning.
+    #[serde(default)]
+    pub playlists: Vec<PathBuf>,
+    /// Systems (by name or core library name) that are left out of the
+    /// library even if their core is installed.
+    #[serde(default)]
+    pub hidden_systems: Vec<String>,
+    /// No-Intro `.dat` files to verify ROMs against.
+    #[serde(default)]
+    pub dat_files: Vec<PathBuf>,
+    /// Only show ROMs that match a good dump in `dat_files`.
+    #[serde(default)]
+    pub verified_only: bool,
+    /// Rotation of individual games by ROM hash, overriding their system's.
+    #[serde(default)]
+    pub game_rotation: HashMap<String, Rotation>,
     pub system: Vec<PreconfSystem>,
     pub menu: MenuConfig,
+    #[serde(default)]
+    pub emulator: EmulatorConfig,
 }
 
-#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct MenuConfig {
     pub max_tile_size: usize,
     pub poweroff_cmd: String,
     pub reboot_cmd: String,
+    /// Runs `poweroff_cmd` from the menu.
+    #[serde(default = "default_poweroff_combo")]
+    pub poweroff_combo: Combo,
+    /// Runs `reboot_cmd` from the menu.
+    #[serde(default = "default_reboot_combo")]
+    pub reboot_combo: Combo,
+    #[serde(default)]
+    pub glow: GlowConfig,
+    /// Seconds without input before the menu stops animating and lowers its
+    /// frame rate to save power. Zero disables this.
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: f32,
+    /// Seconds before informational messages dismiss themselves. Zero waits
+    /// for the user.
+    #[serde(default = "default_message_timeout")]
+    pub message_timeout: f32,
+    /// Cover textures kept in video memory. The least recently drawn ones
+    /// are freed past this. Zero keeps them all.
+    #[serde(default = "default_max_textures")]
+    pub max_textures: usize,
+    /// How the library is laid out the first time the menu opens. West (Tab
+    /// on a keyboard) switches between layouts, and the last one is
+    /// remembered.
+    #[serde(default)]
+    pub view: MenuView,
+    /// Show the selected game's cover and details in a panel beside the
+    /// games, for wide screens.
+    #[serde(default)]
+    pub detail_panel: bool,
+    #[serde(default)]
+    pub sounds: MenuSoundConfig,
+}
+
+/// Sound effects and music for the menu, as WAV files. None are played by
+/// default.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct MenuSoundConfig {
+    /// Played when the cursor moves to another game.
+    pub navigate: Option<PathBuf>,
+    /// Played when a game is launched.
+    pub launch: Option<PathBuf>,
+    /// Volume of the effects, from 0 to 1.
+    pub volume: f32,
+    /// Played on a loop while the menu is open, fading out when a game
+    /// starts. Decoded into memory whole, so keep it short.
+    pub music: Option<PathBuf>,
+    /// Volume of the music, from 0 to 1.
+    pub music_volume: f32,
+}
+
+impl Default for MenuSoundConfig {
+    fn default() -> Self {
+        Self {
+            navigate: None,
+            launch: None,
+            volume: 1.0,
+            music: None,
+            music_volume: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MenuView {
+    /// Cover art in rows of tiles.
+    #[default]
+    Grid,
+    /// One game per row, with a thumbnail, the title and the system.
+    List,
+}
+
+/// Buttons held together on a gamepad, or keys held together on the
+/// keyboard. Either one triggers the combo; an empty list never does.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct Combo {
+    #[serde(default)]
+    pub buttons: Vec<Button>,
+    #[serde(default)]
+    pub keys: Vec<Key>,
+}
+
+/// Keyboard keys that can be used in a [`Combo`].
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum Key {
+    LeftControl,
+    RightControl,
+    LeftAlt,
+    RightAlt,
+    LeftShift,
+    RightShift,
+    LeftSuper,
+    RightSuper,
+    Escape,
+    Tab,
+    Backspace,
+    Enter,
+    Space,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    PrintScreen,
+    Pause,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+}
+
+/// The pulsing effect on the selected game.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct GlowConfig {
+    pub enabled: bool,
+    /// Pulses per second.
+    pub frequency: f32,
+    /// How much brighter the cover gets at the peak of a pulse.
+    pub intensity: f32,
+    /// How much the cover zooms in at the peak of a pulse.
+    pub zoom_factor: f32,
+}
+
+impl Default for GlowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            frequency: 1.0,
+            intensity: 1.0,
+            zoom_factor: 0.2,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct EmulatorConfig {
+    /// Only scale the core's output by whole multiples, leaving black borders
+    /// instead of stretching. Keeps pixels sharp on fixed-resolution displays.
+    #[serde(default)]
+    pub integer_scale: bool,
+    #[serde(default)]
+    pub aspect_ratio: AspectRatioMode,
+    /// How far (0 to 1) an analog L2/R2 trigger must be pulled to register as
+    /// a press.
+    #[serde(default = "default_trigger_threshold")]
+    pub trigger_threshold: f32,
+    /// Button that resets the game when pressed along with Start+Select.
+    #[serde(default = "default_reset_button")]
+    pub reset_button: Button,
+    /// Library names of cores whose RGB565 output has its bytes swapped.
+    #[serde(default)]
+    pub swap_rgb565_cores: Vec<String>,
+    /// In slow motion, the core runs once every this many frames.
+    #[serde(default = "default_slow_motion_divisor")]
+    pub slow_motion_divisor: u32,
+    /// Filter out frequencies above what the output device can play before
+    /// downsampling the core's audio, to avoid aliasing.
+    #[serde(default)]
+    pub audio_lowpass: bool,
+    /// Gamma applied to the game's picture. Above 1 brightens the dark
+    /// parts, below 1 darkens them. Adjusted in game with F3 and F4.
+    #[serde(default = "default_gamma")]
+    pub gamma: f32,
+    /// Don't upload frames identical to the previous one to the GPU, which
+    /// saves power on static screens.
+    #[serde(default)]
+    pub skip_duplicate_frames: bool,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        Self {
+            integer_scale: false,
+            aspect_ratio: AspectRatioMode::default(),
+            trigger_threshold: default_trigger_threshold(),
+            reset_button: default_reset_button(),
+            swap_rgb565_cores: Vec::new(),
+            slow_motion_divisor: default_slow_motion_divisor(),
+            audio_lowpass: false,
+            gamma: default_gamma(),
+            skip_duplicate_frames: false,
+        }
+    }
+}
+
+/// How the shape of the game's picture is decided.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AspectRatioMode {
+    /// Use the aspect ratio the core reports, which accounts for non-square
+    /// pixels.
+    #[default]
+    Core,
+    /// Assume square pixels.
+    Pixel,
+}
+
+/// Clockwise rotation of the game's picture, written in degrees. Used for
+/// arcade games with portrait (TATE) screens.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, Debug)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    pub fn radians(self) -> f32 {
+        f32::from(u16::from(self)).to_radians()
+    }
+
+    /// Whether the picture's width runs vertically on screen.
+    pub fn is_sideways(self) -> bool {
+        matches!(self, Rotation::Deg90 | Rotation::Deg270)
+    }
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> std::result::Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Rotation::Deg0),
+            90 => Ok(Rotation::Deg90),
+            180 => Ok(Rotation::Deg180),
+            270 => Ok(Rotation::Deg270),
+            _ => Err(format!(
+                "rotation must be 0, 90, 180 or 270, not {}",
+                degrees
+            )),
+        }
+    }
+}
+
+impl From<Rotation> for u16 {
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::Deg0 => 0,
+            Rotation::Deg90 => 90,
+            Rotation::Deg180 => 180,
+            Rotation::Deg270 => 270,
+        }
+    }
 }
 
 /// Preconfigured/hardcoded systems
@@ -29,8 +353,109 @@ pub struct PreconfSystem {
     #[serde(skip)]
     pub id: i64,
     pub name: String,
+    /// Name shown in the menu instead of `name`, which is still used to look
+    /// the system up in OpenVGDB.
+    #[serde(default)]
+    pub display_name: Option<String>,
     pub lib: String,
     pub ext: Vec<String>,
+    /// Hash used to look up this system's ROMs: `sha1` (default), `crc32`,
+    /// `md5` or `serial` for Sega CD/Saturn discs.
+    #[serde(default)]
+    pub hash: HashAlgorithm,
+    /// Image shown in the menu in place of the system's name.
+    #[serde(default)]
+    pub logo: Option<PathBuf>,
+    /// Rotation of the system's games, unless overridden in `game_rotation`.
+    #[serde(default)]
+    pub rotation: Rotation,
+}
+
+fn default_poweroff_combo() -> Combo {
+    Combo {
+        buttons: vec![Button::Start, Button::Select, Button::LeftTrigger],
+        keys: vec![Key::LeftControl, Key::LeftAlt, Key::End],
+    }
+}
+
+fn default_reboot_combo() -> Combo {
+    Combo {
+        buttons: vec![Button::Start, Button::Select, Button::RightTrigger],
+        keys: vec![Key::LeftControl, Key::LeftAlt, Key::Delete],
+    }
+}
+
+fn default_gamma() -> f32 {
+    1.0
+}
+
+fn default_slow_motion_divisor() -> u32 {
+    4
+}
+
+fn default_reset_button() -> Button {
+    Button::North
+}
+
+fn default_trigger_threshold() -> f32 {
+    0.5
+}
+
+fn default_max_textures() -> usize {
+    256
+}
+
+fn default_message_timeout() -> f32 {
+    10.0
+}
+
+fn default_idle_timeout() -> f32 {
+    30.0
+}
+
+fn one_or_many_paths<'de, D>(deserializer: D) -> std::result::Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+fn default_vsync() -> bool {
+    true
+}
+
+fn default_openvgdb_path() -> PathBuf {
+    PathBuf::from("openvgdb.sqlite")
+}
+
+fn default_cover_max_age_days() -> u64 {
+    30
+}
+
+fn default_cover_path() -> PathBuf {
+    PathBuf::from("covers/")
+}
+
+fn default_save_path() -> PathBuf {
+    PathBuf::from("saves/")
+}
+
+fn default_window_width() -> i32 {
+    800
+}
+
+fn default_window_height() -> i32 {
+    600
 }
 
 impl Config {
@@ -45,6 +470,90 @@ impl Config {
             sys.id = -(i as i64);
         }
 
+        config.apply_env_overrides();
+        config.validate_paths()?;
+
+        if let Some(system_path) = &config.system_path {
+            log::warn!(
+                "system_path {:?} is set, but cores aren't pointed at it yet and won't load BIOS files from it",
+                system_path
+            );
+        }
+
         Ok(config)
     }
+
+    /// Fails with the offending path if something the library scan needs is
+    /// missing, rather than letting it fail somewhere deep in the scan. ROM
+    /// directories that are missing (say, an unplugged USB drive) are
+    /// skipped, as long as one of them is there.
+    fn validate_paths(&mut self) -> Result<()> {
+        if self.rom_path.is_empty() {
+            bail!("rom_path is empty, it should list the directories holding your ROMs");
+        }
+
+        let (rom_paths, missing): (Vec<_>, Vec<_>) =
+            self.rom_path.drain(..).partition(|path| path.is_dir());
+
+        if rom_paths.is_empty() {
+            bail!(
+                "none of the rom_path directories {:?} exist, they should hold your ROMs",
+                missing
+            );
+        }
+
+        for path in missing {
+            log::warn!("rom_path {:?} does not exist, skipping it", path);
+        }
+        self.rom_path = rom_paths;
+
+        if !self.core_path.is_dir() {
+            bail!(
+                "core_path {:?} does not exist, it should be the directory holding the libretro cores",
+                self.core_path
+            );
+        }
+
+        if !self.openvgdb_path.is_file() {
+            bail!(
+                "openvgdb_path {:?} does not exist, run scripts/download_openvgdb.sh to download it",
+                self.openvgdb_path
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Replaces paths with the ones in `RETROARCADE_*` environment variables,
+    /// which take precedence over the config file. `RETROARCADE_ROM_PATH`
+    /// can list several directories, separated like `PATH`.
+    fn apply_env_overrides(&mut self) {
+        if let Some(paths) = env::var_os("RETROARCADE_ROM_PATH") {
+            self.rom_path = env::split_paths(&paths).collect();
+            log::info!("ROM path set from environment: {:?}", self.rom_path);
+        }
+
+        let paths = [
+            ("RETROARCADE_CORE_PATH", &mut self.core_path),
+            ("RETROARCADE_CACHE_PATH", &mut self.cache_path),
+            ("RETROARCADE_OPENVGDB_PATH", &mut self.openvgdb_path),
+            ("RETROARCADE_SAVE_PATH", &mut self.save_path),
+            ("RETROARCADE_COVER_PATH", &mut self.cover_path),
+        ];
+
+        for (var, path) in paths {
+            if let Some(value) = env::var_os(var) {
+                *path = PathBuf::from(value);
+                log::info!("{} set from environment: {:?}", var, path);
+            }
+        }
+
+        if let Some(value) = env::var_os("RETROARCADE_SYSTEM_PATH") {
+            self.system_path = Some(PathBuf::from(value));
+            log::info!(
+                "RETROARCADE_SYSTEM_PATH set from environment: {:?}",
+                self.system_path
+            );
+        }
+    }
 }
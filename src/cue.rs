@@ -0,0 +1,58 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Returns the track files referenced by a `.cue` sheet, in order, resolved
+/// relative to the sheet's directory.
+pub fn cue_files(cue_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let contents = fs::read_to_string(cue_path)?;
+    let dir = cue_path.parent().unwrap_or_else(|| Path::new(""));
+
+    let files = contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("FILE "))
+        .filter_map(parse_file_name)
+        .map(|name| dir.join(name))
+        .collect();
+
+    Ok(files)
+}
+
+/// Parses the file name out of the rest of a `FILE` line, e.g.
+/// `"Game (Track 1).bin" BINARY`. The name is only quoted when it has spaces.
+fn parse_file_name(rest: &str) -> Option<&str> {
+    let rest = rest.trim();
+
+    if let Some(quoted) = rest.strip_prefix('"') {
+        quoted.split('"').next()
+    } else {
+        rest.split_whitespace().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_file_names() {
+        assert_eq!(
+            parse_file_name(r#""Game (Track 1).bin" BINARY"#),
+            Some("Game (Track 1).bin")
+        );
+        assert_eq!(parse_file_name(r#"  "Game.bin"  BINARY"#), Some("Game.bin"));
+    }
+
+    #[test]
+    fn parses_unquoted_file_names() {
+        assert_eq!(parse_file_name("game.bin BINARY"), Some("game.bin"));
+        assert_eq!(parse_file_name("track02.wav WAVE"), Some("track02.wav"));
+    }
+
+    #[test]
+    fn rejects_empty_lines() {
+        assert_eq!(parse_file_name(""), None);
+        assert_eq!(parse_file_name("   "), None);
+    }
+}
@@ -0,0 +1,87 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::hash::HashAlgorithm;
+
+/// How a ROM compares against the loaded No-Intro DATs.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    /// Matches a known good dump.
+    Verified,
+    /// Matches a dump the DAT marks as bad.
+    BadDump,
+    /// Not in any DAT, or no DAT is loaded.
+    #[default]
+    Unknown,
+}
+
+/// Known dumps from No-Intro `.dat` files, by hash.
+#[derive(Default)]
+pub struct DatIndex {
+    /// Whether each hex hash is a bad dump, for every algorithm DATs list.
+    roms: HashMap<(HashAlgorithm, String), bool>,
+}
+
+impl DatIndex {
+    /// Loads every DAT in `paths`. DATs that fail to parse are skipped.
+    pub fn load(paths: &[impl AsRef<Path>]) -> Self {
+        let mut index = Self::default();
+
+        for path in paths {
+            let path = path.as_ref();
+
+            match index.add_dat(path) {
+                Ok(count) => log::info!("Loaded {} ROMs from DAT {:?}", count, path),
+                Err(e) => log::error!("Couldn't parse DAT {:?}: {}", path, e),
+            }
+        }
+
+        index
+    }
+
+    fn add_dat(&mut self, path: &Path) -> Result<usize> {
+        let contents = fs::read_to_string(path)?;
+        let document = roxmltree::Document::parse(&contents)?;
+        let mut count = 0;
+
+        let roms = document
+            .root_element()
+            .children()
+            .filter(|node| node.has_tag_name("game"))
+            .flat_map(|game| game.children().filter(|node| node.has_tag_name("rom")));
+
+        for rom in roms {
+            let bad_dump = rom.attribute("status") == Some("baddump");
+
+            for (algorithm, attribute) in [
+                (HashAlgorithm::Sha1, "sha1"),
+                (HashAlgorithm::Crc32, "crc"),
+                (HashAlgorithm::Md5, "md5"),
+            ] {
+                if let Some(hash) = rom.attribute(attribute) {
+                    self.roms.insert((algorithm, hash.to_lowercase()), bad_dump);
+                }
+            }
+
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    /// Looks up a ROM by its hex-encoded hash.
+    pub fn verify(&self, algorithm: HashAlgorithm, hash: &str) -> Verification {
+        match self.roms.get(&(algorithm, hash.to_lowercase())) {
+            Some(false) => Verification::Verified,
+            Some(true) => Verification::BadDump,
+            None => Verification::Unknown,
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use gilrs::{Button, Event, EventType, Gilrs};
 use macroquad::prelude::*;
 
 use crate::AppEvent;
@@ -5,19 +6,75 @@ use crate::AppEvent;
 pub enum DynamicDialog {
     YesOrNo(YesOrNoDialog),
     //Login(LoginDialog),
-    //Message(MessageDialog),
+    Message(MessageDialog),
     //Options(Vec<String>),
 }
 
+/// Evaluates `$body` with `$dialog` bound to whichever dialog this is. New
+/// variants only need to be added here.
+macro_rules! dispatch {
+    ($self:expr, $dialog:ident => $body:expr) => {
+        match $self {
+            DynamicDialog::YesOrNo($dialog) => $body,
+            DynamicDialog::Message($dialog) => $body,
+        }
+    };
+}
+
+impl DynamicDialog {
+    pub fn update(&mut self, gilrs: &mut Gilrs) -> DialogUpdate {
+        let input = get_input(gilrs);
+        dispatch!(self, dialog => dialog.update(&input))
+    }
+
+    pub fn render(&self) {
+        dispatch!(self, dialog => dialog.render())
+    }
+
+    pub fn produce_event(self) -> AppEvent {
+        dispatch!(self, dialog => dialog.produce_event())
+    }
+}
+
 pub trait Dialog {
     type Value;
 
-    fn update(&mut self) -> DialogUpdate;
+    fn update(&mut self, input: &DialogInput) -> DialogUpdate;
     fn render(&self);
     fn current_value(&self) -> Self::Value;
     fn produce_event(self) -> AppEvent;
 }
 
+/// What was pressed this frame, on the keyboard or any gamepad.
+#[derive(Clone, Default, Debug)]
+pub struct DialogInput {
+    /// Left or right, to switch between choices.
+    pub change: bool,
+    pub confirm: bool,
+}
+
+fn get_input(gilrs: &mut Gilrs) -> DialogInput {
+    let mut input = DialogInput {
+        change: is_key_pressed(KeyCode::Left) || is_key_pressed(KeyCode::Right),
+        confirm: is_key_pressed(KeyCode::Enter),
+    };
+
+    // Only presses made while the dialog is open count, so the press that
+    // opened it (e.g. launching a game) doesn't also confirm it. The menu
+    // only launches on new presses too, so it won't see the confirming one.
+    while let Some(Event { event, .. }) = gilrs.next_event() {
+        match event {
+            EventType::ButtonPressed(Button::DPadLeft | Button::DPadRight, _) => {
+                input.change = true
+            }
+            EventType::ButtonPressed(Button::South | Button::East, _) => input.confirm = true,
+            _ => (),
+        }
+    }
+
+    input
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum DialogUpdate {
     Finish,
@@ -33,14 +90,12 @@ pub struct YesOrNoDialog {
 impl Dialog for YesOrNoDialog {
     type Value = bool;
 
-    fn update(&mut self) -> DialogUpdate {
-        let change = is_key_pressed(KeyCode::Left) || is_key_pressed(KeyCode::Right);
-
-        if change {
+    fn update(&mut self, input: &DialogInput) -> DialogUpdate {
+        if input.change {
             self.value = !self.value;
         }
 
-        if is_key_pressed(KeyCode::Enter) {
+        if input.confirm {
             DialogUpdate::Finish
         } else {
             DialogUpdate::Continue
@@ -90,3 +145,83 @@ impl Dialog for YesOrNoDialog {
         (self.event_handler)(self.value)
     }
 }
+
+pub struct MessageDialog {
+    pub text: String,
+    pub event_handler: Box<dyn FnOnce() -> AppEvent>,
+    /// Seconds after which the dialog dismisses itself, if any.
+    pub timeout: Option<f32>,
+    pub elapsed: f32,
+}
+
+impl MessageDialog {
+    /// A message that simply goes away when acknowledged.
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            event_handler: Box::new(|| AppEvent::Continue),
+            timeout: None,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Dismisses the dialog on its own after `seconds`. Zero or less means
+    /// it waits for the user.
+    pub fn with_timeout(mut self, seconds: f32) -> Self {
+        self.timeout = Some(seconds).filter(|seconds| *seconds > 0.0);
+        self
+    }
+}
+
+impl Dialog for MessageDialog {
+    type Value = ();
+
+    fn update(&mut self, input: &DialogInput) -> DialogUpdate {
+        self.elapsed += get_frame_time();
+        let timed_out = self
+            .timeout
+            .map_or(false, |timeout| self.elapsed >= timeout);
+
+        if input.confirm || timed_out {
+            DialogUpdate::Finish
+        } else {
+            DialogUpdate::Continue
+        }
+    }
+
+    fn render(&self) {
+        let (sw, sh) = (screen_width(), screen_height());
+        let width = sw / 1.2;
+        let height = sh / 1.2;
+        let x = (sw / 2.0) - (width / 2.0);
+        let y = (sh / 2.0) - (height / 2.0);
+
+        let margin = 2.0;
+        let white = Color::from_rgba(255, 255, 255, 255);
+        let yellow = Color::from_rgba(255, 255, 0, 255);
+
+        draw_rectangle(x, y, width, height, Color::from_rgba(0, 0, 0, 255));
+
+        for (i, line) in self.text.lines().enumerate() {
+            draw_text(
+                line,
+                x + margin,
+                y + margin + 64.0 + 32.0 * i as f32,
+                32.0,
+                white,
+            );
+        }
+
+        let ok = match self.timeout {
+            Some(timeout) => format!("OK ({:.0})", (timeout - self.elapsed).max(0.0).ceil()),
+            None => "OK".to_string(),
+        };
+        draw_text(&ok, x + margin, y + height - margin - 32.0, 32.0, yellow);
+    }
+
+    fn current_value(&self) -> Self::Value {}
+
+    fn produce_event(self) -> AppEvent {
+        (self.event_handler)()
+    }
+}
@@ -1,10 +1,13 @@
 use std::{
-    collections::HashSet,
-    path::Path,
+    collections::{HashSet, VecDeque},
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use cpal::traits::DeviceTrait;
 use gilrs::{Button, Event, GamepadId, Gilrs};
 use libretro_sys::PixelFormat;
@@ -12,21 +15,66 @@ use macroquad::prelude::*;
 use retro_rs::{pixels, Emulator, InputPort, RetroRsError};
 
 use crate::{
-    audio,
+    audio::{self, LowPassFilter},
+    config::{AspectRatioMode, EmulatorConfig, Rotation},
     gamepad::{update_input_port_with_gamepad, update_input_port_with_keyboard},
+    movie::Movie,
     AppEvent,
 };
 
+/// Everything needed to start a game.
+pub struct GameLaunch {
+    pub core: PathBuf,
+    /// Library name of the core, as reported when the library was scanned.
+    pub core_name: String,
+    pub rom: PathBuf,
+    /// Save state to start from.
+    pub save: Option<PathBuf>,
+    /// Battery save file for the game. Loaded if it exists and written back
+    /// when returning to the menu.
+    pub sram: PathBuf,
+    /// Hash of the ROM, checked when replaying movies.
+    pub rom_hash: String,
+    pub rotation: Rotation,
+}
+
 pub struct EmulatorState {
     emu: Emulator,
     controllers: [InputPort; 2],
     gamepad_ids: HashSet<GamepadId>,
+    config: EmulatorConfig,
+    sram_path: PathBuf,
+    rom_hash: String,
+    movie: MovieState,
+    /// Whether the reset combination was held last frame, so holding it
+    /// only resets once.
+    reset_held: bool,
+    /// A reset waiting for the next core frame, so movies record which frame
+    /// it happened on.
+    reset_requested: bool,
+    paused: bool,
+    slow_motion: bool,
+    /// Frames drawn since the game started, to pace slow motion.
+    frame_count: u32,
+    /// When each of the last core frames ran, oldest first.
+    frame_times: VecDeque<Instant>,
+    show_fps: bool,
 
     // Graphics
-    fb_copy: Vec<u8>,
+    /// Last complete frame, which is what gets uploaded to `fb_texture`.
     fb_image: Image,
+    /// Frame being converted from the core's framebuffer. Swapped with
+    /// `fb_image` once it's done.
+    fb_back_image: Image,
     fb_texture: Texture2D,
-    fb_interlace_factor: usize,
+    /// Whether the core writes RGB565 pixels in the opposite byte order.
+    swap_rgb565: bool,
+    rotation: Rotation,
+    gamma: f32,
+    /// Each color channel value after gamma correction.
+    gamma_table: [u8; 256],
+    /// On-screen messages, shown one at a time for their number of frames.
+    messages: VecDeque<OsdMessage>,
 
     // Audio
     #[allow(dead_code)]
@@ -34,30 +82,61 @@ pub struct EmulatorState {
     #[allow(dead_code)]
     audio_stream: cpal::Stream,
     audio_buffer: Arc<Mutex<Vec<i16>>>,
+    lowpass: Option<LowPassFilter>,
 }
 
 impl EmulatorState {
-    pub fn create(core: &Path, rom: &Path, save: Option<Vec<u8>>) -> Self {
-        let mut emu = Emulator::create(core, rom);
+    pub fn create(game: GameLaunch, config: EmulatorConfig) -> Result<Self> {
+        let GameLaunch {
+            core,
+            core_name,
+            rom,
+            save,
+            sram: sram_path,
+            rom_hash,
+            rotation,
+        } = game;
+
+        let swap_rgb565 = config.swap_rgb565_cores.contains(&core_name);
+        // retro-rs panics when the core or ROM can't be loaded
+        let mut emu = catch_panic(|| Emulator::create(&core, &rom)).context("loading game")?;
         let controllers = [InputPort::new(), InputPort::new()];
 
         emu.run(controllers);
         emu.reset();
 
-        // Load save state if given
-        if let Some(save) = save {
-            emu.run(controllers);
-            emu.run(controllers);
-            emu.run(controllers);
+        // Load battery save RAM if the game has one
+        if let Ok(sram) = fs::read(&sram_path) {
+            let region = emu.save_ram_mut();
+            let len = region.len().min(sram.len());
+
+            if len != sram.len() {
+                log::warn!(
+                    "Save RAM file {:?} is {} bytes, core expects {}",
+                    sram_path,
+                    sram.len(),
+                    region.len()
+                );
+            }
 
-            println!("INFO: Loading provided save file state");
-            emu.load(&save);
+            region[..len].copy_from_slice(&sram[..len]);
+            log::info!("Loaded save RAM from {:?}", sram_path);
         }
 
-        let (width, height) = emu.framebuffer_size();
-        let pitch = emu.framebuffer_pitch();
+        // Load save state if given. It's read only now, once the game is
+        // loaded, so a bad core or ROM doesn't cost reading it first.
+        if let Some(save) = &save {
+            let state = fs::read(save).with_context(|| format!("reading save state {:?}", save))?;
 
-        let fb_copy = vec![0u8; height * pitch];
+            for _ in 0..SAVE_STATE_WARMUP_FRAMES {
+                emu.run(controllers);
+            }
+
+            log::info!("Loading save state from {:?}", save);
+            emu.load(&state);
+        }
+
+        let (width, height) = emu.framebuffer_size();
 
         let fb_image = Image {
             bytes: [0x00, 0x00, 0x00, 0xFF].repeat(width * height),
@@ -65,18 +144,29 @@ impl EmulatorState {
             height: height as u16,
         };
 
+        let fb_back_image = fb_image.clone();
         let fb_texture = Texture2D::from_image(&fb_image);
         fb_texture.set_filter(FilterMode::Nearest);
-        let fb_interlace_factor = 1;
 
-        let audio_device = audio::init().unwrap();
+        let audio_device = audio::init()?;
         let audio_buffer = Arc::new(Mutex::new(Vec::new()));
 
+        // Only downsampling aliases, so the filter is skipped otherwise
+        let lowpass = if config.audio_lowpass {
+            let device_sample_rate = audio_device.default_output_config()?.sample_rate().0 as f64;
+            let core_sample_rate = emu.system_av_info().timing.sample_rate;
+
+            (core_sample_rate > device_sample_rate)
+                .then(|| LowPassFilter::new(device_sample_rate * 0.45, core_sample_rate))
+        } else {
+            None
+        };
+
         let audio_stream = audio::run(&audio_device, {
             let audio_buffer = audio_buffer.clone();
 
             // Get device sample rate
-            let default_output_config = audio_device.default_output_config().unwrap();
+            let default_output_config = audio_device.default_output_config()?;
             let device_sample_rate = default_output_config.sample_rate().0 as f64;
 
             // Get core sample rate
@@ -138,23 +228,39 @@ impl EmulatorState {
                 }
                 true
             }
-        })
-        .unwrap();
+        })?;
 
         let gamepad_ids = HashSet::new();
+        let gamma = config.gamma.clamp(MIN_GAMMA, MAX_GAMMA);
 
-        EmulatorState {
+        Ok(EmulatorState {
             emu,
             controllers,
             gamepad_ids,
-            fb_copy,
+            config,
+            sram_path,
+            rom_hash,
+            movie: MovieState::Off,
+            reset_held: false,
+            reset_requested: false,
+            paused: false,
+            slow_motion: false,
+            frame_count: 0,
+            frame_times: VecDeque::with_capacity(FRAME_TIMING_WINDOW),
+            show_fps: false,
             fb_image,
+            fb_back_image,
             fb_texture,
-            fb_interlace_factor,
+            swap_rgb565,
+            rotation,
+            gamma,
+            gamma_table: gamma_table(gamma),
+            messages: VecDeque::new(),
             audio_device,
             audio_stream,
             audio_buffer,
-        }
+            lowpass,
+        })
     }
 
     pub fn update(&mut self, gilrs: &mut Gilrs) -> AppEvent {
@@ -167,7 +273,7 @@ impl EmulatorState {
             let g_id = registered_gamepad_iter.next();
 
             if let Some(gamepad) = g_id.and_then(|g_id| gilrs.connected_gamepad(*g_id)) {
-                update_input_port_with_gamepad(input, &gamepad);
+                update_input_port_with_gamepad(input, &gamepad, self.config.trigger_threshold);
             } else if !keyboard_in_use {
                 keyboard_in_use = true;
                 update_input_port_with_keyboard(input);
@@ -179,24 +285,237 @@ impl EmulatorState {
             return AppEvent::GoToMenu;
         }
 
-        self.emu.run(self.controllers);
-        self.update_framebuffer();
-        self.update_audio_buffer().unwrap();
+        if is_key_pressed(KeyCode::F12) {
+            return AppEvent::SetCover(self.fb_image.clone());
+        }
+
+        if is_key_pressed(KeyCode::F7) {
+            self.slow_motion = !self.slow_motion;
+            self.show_message(
+                if self.slow_motion {
+                    "Slow motion"
+                } else {
+                    "Normal speed"
+                },
+                120,
+            );
+        }
+
+        // Gamma, for cores whose picture is too dark (or bright) on this
+        // display
+        let gamma_step = match (is_key_pressed(KeyCode::F3), is_key_pressed(KeyCode::F4)) {
+            (true, false) => Some(-GAMMA_STEP),
+            (false, true) => Some(GAMMA_STEP),
+            _ => None,
+        };
+        if let Some(step) = gamma_step {
+            self.gamma = (self.gamma + step).clamp(MIN_GAMMA, MAX_GAMMA);
+            self.gamma_table = gamma_table(self.gamma);
+            self.show_message(format!("Gamma {:.1}", self.gamma), 120);
+        }
+
+        if is_key_pressed(KeyCode::F2) {
+            self.show_fps = !self.show_fps;
+        }
+
+        if is_key_pressed(KeyCode::F5) {
+            self.paused = !self.paused;
+            self.show_message(if self.paused { "Paused" } else { "Resumed" }, 120);
+        }
+
+        // In slow motion the core only runs every few frames, holding the
+        // last picture in between. While paused it only runs when advancing
+        // a single frame.
+        self.frame_count = self.frame_count.wrapping_add(1);
+        let run_core = if self.paused {
+            is_key_pressed(KeyCode::F6)
+        } else {
+            !self.slow_motion || self.frame_count % self.config.slow_motion_divisor.max(1) == 0
+        };
+
+        // Movie recording and playback
+        if is_key_pressed(KeyCode::F9) {
+            self.toggle_recording();
+        }
+        if is_key_pressed(KeyCode::F10) {
+            self.toggle_playback();
+        }
+
+        let reset = should_reset_game(gilrs, self.config.reset_button);
+        if reset && !self.reset_held {
+            self.reset_requested = true;
+        }
+        self.reset_held = reset;
+
+        if run_core {
+            let mut reset = std::mem::take(&mut self.reset_requested);
+
+            match &mut self.movie {
+                MovieState::Off => (),
+                MovieState::Recording(movie) => movie.record(&self.controllers, reset),
+                MovieState::Playing { movie, frame } => {
+                    // Resets come from the recording, like the input
+                    match movie.play(*frame, &mut self.controllers) {
+                        Some(recorded_reset) => {
+                            reset = recorded_reset;
+                            *frame += 1;
+                        }
+                        None => {
+                            self.movie = MovieState::Off;
+                            self.show_message("Playback finished", 120);
+                        }
+                    }
+                }
+            }
+
+            if reset {
+                self.emu.reset();
+                self.show_message("Reset", 120);
+            }
+        }
+
+        // Slowed-down or single-stepped audio would just pile up in the
+        // buffer, so drop it
+        if run_core {
+            if let Err(e) = self.step(!self.slow_motion && !self.paused) {
+                return AppEvent::EmulatorError(e.to_string());
+            }
+        }
 
         // Gamepads
         for (g_id, _) in gilrs.gamepads() {
             self.gamepad_ids.insert(g_id);
         }
 
+        // Count down the message being shown
+        if let Some(message) = self.messages.front_mut() {
+            message.frames = message.frames.saturating_sub(1);
+
+            if message.frames == 0 {
+                self.messages.pop_front();
+            }
+        }
+
         AppEvent::Continue
     }
 
-    fn update_framebuffer(&mut self) {
+    /// Movie file for the game, next to its save RAM.
+    fn movie_path(&self) -> PathBuf {
+        self.sram_path.with_extension("movie")
+    }
+
+    /// Starts recording from the current state, or stops and writes the movie.
+    fn toggle_recording(&mut self) {
+        match std::mem::replace(&mut self.movie, MovieState::Off) {
+            MovieState::Recording(movie) => {
+                let path = self.movie_path();
+
+                match movie.save(&path) {
+                    Ok(()) => {
+                        log::info!("Wrote {} frame movie to {:?}", movie.frame_count(), path);
+                        self.show_message("Recording saved", 120);
+                    }
+                    Err(e) => {
+                        log::error!("Couldn't write movie: {:#}", e);
+                        self.show_message("Couldn't save recording", 120);
+                    }
+                }
+            }
+            _ => {
+                self.movie =
+                    MovieState::Recording(Movie::new(self.rom_hash.clone(), self.snapshot()));
+                self.show_message("Recording", 120);
+            }
+        }
+    }
+
+    /// Replays the game's movie from its starting state, or stops playback.
+    fn toggle_playback(&mut self) {
+        if let MovieState::Playing { .. } = self.movie {
+            self.movie = MovieState::Off;
+            self.show_message("Playback stopped", 120);
+            return;
+        }
+
+        let movie = match Movie::load(&self.movie_path()) {
+            Ok(movie) => movie,
+            Err(e) => {
+                log::error!("Couldn't load movie: {:#}", e);
+                self.show_message("Couldn't load recording", 120);
+                return;
+            }
+        };
+
+        if movie.rom_hash != self.rom_hash {
+            log::error!(
+                "Movie was recorded with ROM {}, this is {}",
+                movie.rom_hash,
+                self.rom_hash
+            );
+            self.show_message("Recording is for a different ROM", 180);
+            return;
+        }
+
+        self.emu.load(&movie.state);
+        self.movie = MovieState::Playing { movie, frame: 0 };
+        self.show_message("Playing recording", 120);
+    }
+
+    /// Queues a message to show over the game for `frames` frames. Messages
+    /// from cores (`RETRO_ENVIRONMENT_SET_MESSAGE`) don't get here, since
+    /// retro-rs doesn't pass them on.
+    pub fn show_message(&mut self, text: impl Into<String>, frames: u32) {
+        self.messages.push_back(OsdMessage {
+            text: text.into(),
+            frames,
+        });
+    }
+
+    /// Average time between the last core frames, or `None` until a couple
+    /// have run. Paused time and slow motion count too.
+    pub fn frame_time(&self) -> Option<Duration> {
+        let first = self.frame_times.front()?;
+        let last = self.frame_times.back()?;
+        let intervals = self.frame_times.len().checked_sub(1).filter(|n| *n > 0)?;
+
+        Some(last.duration_since(*first) / intervals as u32)
+    }
+
+    /// Core frames per second, measured over the last frames.
+    pub fn fps(&self) -> Option<f64> {
+        self.frame_time()
+            .filter(|frame_time| !frame_time.is_zero())
+            .map(|frame_time| 1.0 / frame_time.as_secs_f64())
+    }
+
+    /// Runs one frame of the core and collects its video output, and its
+    /// audio output if `audio` is set.
+    fn step(&mut self, audio: bool) -> Result<()> {
+        let controllers = self.controllers;
+        let emu = &mut self.emu;
+        catch_panic(|| emu.run(controllers)).context("running core")?;
+
+        if self.frame_times.len() == FRAME_TIMING_WINDOW {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(Instant::now());
+
+        self.update_framebuffer()?;
+        if audio {
+            self.update_audio_buffer()?;
+        }
+        Ok(())
+    }
+
+    fn update_framebuffer(&mut self) -> Result<()> {
         let (fb_width, fb_height) = self.emu.framebuffer_size();
         let fb_pitch = self.emu.framebuffer_pitch();
 
+        // Cores can change their geometry mid-game (e.g. SNES hi-res modes).
+        // The aspect ratio is read again on every render, so only a new
+        // framebuffer size needs a new texture
         if fb_width != self.fb_image.width as usize || fb_height != self.fb_image.height as usize {
-            self.resize_framebuffer(fb_width, fb_height, fb_pitch);
+            self.resize_framebuffer(fb_width, fb_height);
 
             info!(
                 "Display mode changed: {:?} (width {}) (height {}) (pitch {} == {})",
@@ -209,6 +528,8 @@ impl EmulatorState {
         }
 
         let pixfmt = self.emu.pixel_format();
+        let swap_rgb565 = self.swap_rgb565;
+        let gamma_table = &self.gamma_table;
 
         // Copy framebuffer
         let framebuffer_result = self.emu.peek_framebuffer(|fb: &[u8]| {
@@ -220,8 +541,13 @@ impl EmulatorState {
 
             type ColorFn = Box<dyn Fn(&[u8]) -> (u8, u8, u8)>;
             let color_fn: ColorFn = match pixfmt {
-                PixelFormat::ARGB1555 => unimplemented!(),
+                PixelFormat::ARGB1555 => Box::new(|b| {
+                    let pixel = u16::from_le_bytes([b[0], b[1]]);
+                    let expand = |c: u16| ((c & 0x1F) << 3) as u8;
+                    (expand(pixel >> 10), expand(pixel >> 5), expand(pixel))
+                }),
                 PixelFormat::ARGB8888 => Box::new(|b| (b[2], b[1], b[0])),
+                PixelFormat::RGB565 if swap_rgb565 => Box::new(|b| pixels::rgb565to888(b[1], b[0])),
                 PixelFormat::RGB565 => Box::new(|b| pixels::rgb565to888(b[0], b[1])),
             };
 
@@ -236,57 +562,110 @@ impl EmulatorState {
 
                     let (red, green, blue) = color_fn(&fb[fb_index..fb_index + pixel_size]);
 
-                    self.fb_image.bytes[tex_index] = red; // R
-                    self.fb_image.bytes[tex_index + 1] = green; // G
-                    self.fb_image.bytes[tex_index + 2] = blue; // B
-                    self.fb_image.bytes[tex_index + 3] = 0xFF; // A
+                    self.fb_back_image.bytes[tex_index] = gamma_table[red as usize]; // R
+                    self.fb_back_image.bytes[tex_index + 1] = gamma_table[green as usize]; // G
+                    self.fb_back_image.bytes[tex_index + 2] = gamma_table[blue as usize]; // B
+                    self.fb_back_image.bytes[tex_index + 3] = 0xFF; // A
                 }
             }
         });
 
         match framebuffer_result {
-            Err(RetroRsError::NoFramebufferError) => log::warn!("No framebuffer!"),
-            Err(e) => panic!("{}", e),
+            // The core repeated the last frame, which is still on the texture
+            Err(RetroRsError::NoFramebufferError) => return Ok(()),
+            Err(e) => return Err(e.into()),
             Ok(_) => (),
         }
 
+        std::mem::swap(&mut self.fb_image, &mut self.fb_back_image);
+
+        // The back buffer now holds the previous frame. Comparing the whole
+        // frame rather than a hash means a changed frame is never skipped.
+        if self.config.skip_duplicate_frames && self.fb_image.bytes == self.fb_back_image.bytes {
+            return Ok(());
+        }
+
         self.fb_texture.update(&self.fb_image);
+        Ok(())
     }
 
     fn update_audio_buffer(&mut self) -> Result<()> {
+        let lowpass = &mut self.lowpass;
+
         self.emu.peek_audio_buffer(|b| {
             let mut buf = self.audio_buffer.lock().unwrap();
+            let start = buf.len();
             buf.extend_from_slice(b);
+
+            if let Some(lowpass) = lowpass {
+                lowpass.process(&mut buf[start..]);
+            }
         })?;
 
         Ok(())
     }
 
-    fn resize_framebuffer(&mut self, width: usize, height: usize, pitch: usize) {
-        self.fb_copy.resize(height * pitch, 0u8);
+    fn resize_framebuffer(&mut self, width: usize, height: usize) {
         self.fb_image = Image {
             bytes: [0x00, 0x00, 0x00, 0xFF].repeat(width * height),
             width: width as u16,
             height: height as u16,
         };
+        self.fb_back_image = self.fb_image.clone();
+        self.fb_texture.delete();
         self.fb_texture = Texture2D::from_image(&self.fb_image);
-        self.fb_interlace_factor = (pitch - width) / 4;
+        self.fb_texture.set_filter(FilterMode::Nearest);
+    }
+
+    /// Display aspect ratio of the game, according to the configured mode.
+    fn aspect_ratio(&self) -> f32 {
+        let pixel_aspect_ratio = self.fb_texture.width() / self.fb_texture.height();
+
+        match self.config.aspect_ratio {
+            AspectRatioMode::Pixel => pixel_aspect_ratio,
+            AspectRatioMode::Core => {
+                // Cores report 0 (or less) when they don't care
+                let core_aspect_ratio = self.emu.system_av_info().geometry.aspect_ratio;
+
+                if core_aspect_ratio > 0.0 {
+                    core_aspect_ratio
+                } else {
+                    pixel_aspect_ratio
+                }
+            }
+        }
     }
 
     pub fn render(&self, gilrs: &Gilrs) {
         clear_background(BLACK);
 
-        let tex_width = self.fb_texture.width();
         let tex_height = self.fb_texture.height();
         let screen_width = screen_width();
         let screen_height = screen_height();
 
-        let (width, height) = if (screen_width / screen_height) > (tex_width / tex_height) {
-            ((tex_width * screen_height) / tex_height, screen_height)
+        // Sizes are worked out before rotating, so a sideways picture has
+        // the screen's height to fill horizontally and vice versa
+        let (fit_width, fit_height) = if self.rotation.is_sideways() {
+            (screen_height, screen_width)
+        } else {
+            (screen_width, screen_height)
+        };
+
+        let aspect_ratio = self.aspect_ratio();
+
+        let (mut width, mut height) = if (fit_width / fit_height) > aspect_ratio {
+            (fit_height * aspect_ratio, fit_height)
         } else {
-            (screen_width, (tex_height * screen_width) / tex_width)
+            (fit_width, fit_width / aspect_ratio)
         };
 
+        if self.config.integer_scale {
+            // Largest whole multiple that still fits, but never smaller than 1x
+            let scale = (height / tex_height).floor().max(1.0);
+            height = tex_height * scale;
+            width = height * aspect_ratio;
+        }
+
         draw_texture_ex(
             self.fb_texture,
             screen_width / 2. - width / 2.,
@@ -295,13 +674,49 @@ impl EmulatorState {
             DrawTextureParams {
                 dest_size: Some(Vec2::new(width, height)),
                 source: None,
-                rotation: 0.0,
+                rotation: self.rotation.radians(),
                 flip_x: false,
                 flip_y: false,
                 pivot: None,
             },
         );
 
+        // On-screen message
+        if let Some(message) = self.messages.front() {
+            let size = 32.0;
+            let dimensions = measure_text(&message.text, None, size as u16, 1.0);
+
+            draw_rectangle(
+                10.0,
+                10.0,
+                dimensions.width + 20.0,
+                size + 10.0,
+                Color::from_rgba(0, 0, 0, 180),
+            );
+            draw_text(&message.text, 20.0, 10.0 + size, size, WHITE);
+        }
+
+        if let (true, Some(fps), Some(frame_time)) = (self.show_fps, self.fps(), self.frame_time())
+        {
+            let text = format!(
+                "{:.1} FPS ({:.2} ms)",
+                fps,
+                frame_time.as_secs_f64() * 1000.0
+            );
+            let size = 24.0;
+            let dimensions = measure_text(&text, None, size as u16, 1.0);
+            let x = screen_width - dimensions.width - 20.0;
+
+            draw_rectangle(
+                x - 10.0,
+                10.0,
+                dimensions.width + 20.0,
+                size + 10.0,
+                Color::from_rgba(0, 0, 0, 180),
+            );
+            draw_text(&text, x, 10.0 + size, size, WHITE);
+        }
+
         let error_width = 100.0;
         let error_height = 50.0;
 
@@ -343,6 +758,50 @@ impl EmulatorState {
         }
     }
 
+    /// Writes the core's battery save RAM to the game's `.srm` file. Games
+    /// without save RAM are skipped.
+    pub fn write_sram(&self) -> Result<()> {
+        let sram = self.emu.save_ram_ref();
+
+        if sram.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.sram_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.sram_path, sram)?;
+        log::info!("Wrote save RAM to {:?}", self.sram_path);
+
+        Ok(())
+    }
+
+    /// Copies `len` bytes at `offset` out of one of the core's memory regions.
+    #[allow(dead_code)]
+    pub fn read_memory(&self, region: MemoryRegion, offset: usize, len: usize) -> Result<Vec<u8>> {
+        let memory = match region {
+            MemoryRegion::SaveRam => self.emu.save_ram_ref(),
+            MemoryRegion::SystemRam => self.emu.system_ram_ref(),
+            MemoryRegion::VideoRam => self.emu.video_ram_ref(),
+        };
+
+        Ok(memory[memory_range(region, memory.len(), offset, len)?].to_vec())
+    }
+
+    /// Overwrites the bytes at `offset` in one of the core's memory regions.
+    #[allow(dead_code)]
+    pub fn write_memory(&mut self, region: MemoryRegion, offset: usize, data: &[u8]) -> Result<()> {
+        let memory = match region {
+            MemoryRegion::SaveRam => self.emu.save_ram_mut(),
+            MemoryRegion::SystemRam => self.emu.system_ram_mut(),
+            MemoryRegion::VideoRam => self.emu.video_ram_mut(),
+        };
+
+        let range = memory_range(region, memory.len(), offset, data.len())?;
+        memory[range].copy_from_slice(data);
+        Ok(())
+    }
+
     pub fn snapshot(&self) -> Vec<u8> {
         let mut save_buffer = vec![0u8; self.emu.save_size()];
         self.emu.save(&mut save_buffer);
@@ -350,6 +809,94 @@ impl EmulatorState {
     }
 }
 
+/// Memory exposed by cores through the libretro memory interface.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MemoryRegion {
+    SaveRam,
+    SystemRam,
+    VideoRam,
+}
+
+/// Frames run before loading a save state. Some cores ignore states loaded
+/// before they've finished setting up the game, which takes a few frames.
+const SAVE_STATE_WARMUP_FRAMES: usize = 3;
+
+/// Core frames the frame rate is measured over.
+const FRAME_TIMING_WINDOW: usize = 60;
+
+const GAMMA_STEP: f32 = 0.1;
+const MIN_GAMMA: f32 = 0.2;
+const MAX_GAMMA: f32 = 3.0;
+
+/// Maps each 8-bit color channel value through `gamma`.
+fn gamma_table(gamma: f32) -> [u8; 256] {
+    let mut table = [0u8; 256];
+
+    for (value, corrected) in table.iter_mut().enumerate() {
+        let normalized = value as f32 / 255.0;
+        *corrected = (normalized.powf(1.0 / gamma) * 255.0).round() as u8;
+    }
+
+    table
+}
+
+/// Checks that `len` bytes at `offset` fit in a region of `size` bytes.
+fn memory_range(
+    region: MemoryRegion,
+    size: usize,
+    offset: usize,
+    len: usize,
+) -> Result<std::ops::Range<usize>> {
+    match offset.checked_add(len) {
+        Some(end) if end <= size => Ok(offset..end),
+        _ => Err(anyhow!(
+            "{:?} access of {} bytes at {:#x} is out of bounds ({} bytes)",
+            region,
+            len,
+            offset,
+            size
+        )),
+    }
+}
+
+enum MovieState {
+    Off,
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+struct OsdMessage {
+    text: String,
+    /// Frames left to show the message for.
+    frames: u32,
+}
+
+/// Turns a panic inside `f` into an error carrying the panic message.
+pub fn catch_panic<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> T,
+{
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        anyhow!(message)
+    })
+}
+
+fn should_reset_game(gilrs: &Gilrs, reset_button: Button) -> bool {
+    // Start + Select + the configured button = Reset game
+    is_key_down(KeyCode::F8)
+        || gilrs.gamepads().any(|(_, g)| {
+            g.is_pressed(Button::Select)
+                && g.is_pressed(Button::Start)
+                && g.is_pressed(reset_button)
+        })
+}
+
 fn should_quit_game(gilrs: &Gilrs) -> bool {
     // Check for exit game keyboard and gamepad combinations
     // Start + Select + West = Quit game
@@ -0,0 +1,104 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::game_db::{Game, GameDb};
+
+/// A game along with the name of its system, as written to exports.
+#[derive(Serialize)]
+struct ExportedGame<'a> {
+    title: &'a str,
+    system: &'a str,
+    #[serde(flatten)]
+    game: &'a Game,
+}
+
+/// Writes every game in the library to `path`, as CSV if the file extension is
+/// `csv` and as JSON otherwise.
+pub fn export_game_list<P>(game_db: &GameDb, path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut games: Vec<_> = game_db
+        .games_iter()
+        .map(|(_, game)| ExportedGame {
+            title: game.title(),
+            system: &game_db.get_system(game.system_id).name,
+            game,
+        })
+        .collect();
+    games.sort_by(|a, b| a.title.cmp(b.title));
+
+    let contents = match path.extension().and_then(|e| e.to_str()) {
+        Some("csv") => to_csv(&games),
+        _ => serde_json::to_string_pretty(&games)?,
+    };
+
+    fs::write(path, contents)?;
+    log::info!("Exported {} games to {:?}", games.len(), path);
+    Ok(())
+}
+
+fn to_csv(games: &[ExportedGame]) -> String {
+    let mut csv = String::from("title,system,hash,path,release_id,cover_url\n");
+
+    for exported in games {
+        let game = exported.game;
+        let (release_id, cover_url) = match &game.metadata {
+            Some(metadata) => (
+                metadata
+                    .release_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+                metadata.cover_url.as_deref().unwrap_or_default(),
+            ),
+            None => (String::new(), ""),
+        };
+
+        let path = game.rom_path.to_string_lossy();
+
+        let fields: [&str; 6] = [
+            exported.title,
+            exported.system,
+            &game.hash,
+            &path,
+            &release_id,
+            cover_url,
+        ];
+        let row: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Quotes a field if it contains characters that would break the row.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_plain_fields_alone() {
+        assert_eq!(csv_field("Super Mario World"), "Super Mario World");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn quotes_fields_that_would_break_the_row() {
+        assert_eq!(csv_field("Yes, Prime Minister"), "\"Yes, Prime Minister\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_field("The \"Hit\""), "\"The \"\"Hit\"\"\"");
+    }
+}
@@ -1,48 +1,171 @@
-use std::{collections::HashMap, ffi::OsStr, fs, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    time::UNIX_EPOCH,
+};
 
 use anyhow::{Context, Result};
-use log::error;
 use macroquad::{prelude::Color, rand};
+use rayon::prelude::*;
 use retro_rs::Emulator;
+use serde::Serialize;
 use sqlx::SqliteConnection;
 
-use crate::{cache::Cache, config::Config, hash::*};
-
+use crate::{
+    cache::Cache,
+    config::{Config, Rotation},
+    cue,
+    dat::{DatIndex, Verification},
+    gamelist::GamelistIndex,
+    hash::*,
+    playlist::{self, PlaylistItem},
+};
+
+#[derive(Serialize)]
 pub struct Game {
     pub system_id: i64,
-    pub sha1: String,
+    /// Hex-encoded hash identifying the ROM, computed with the system's
+    /// configured [`HashAlgorithm`].
+    pub hash: String,
     pub metadata: Option<GameMetadata>,
     pub filename: String,
     pub extension: String,
     pub rom_path: PathBuf,
+    /// Set from the No-Intro DATs when the game is added to the library.
+    pub verification: Verification,
+    #[serde(skip)]
     pub color: Color,
 }
 
+impl Game {
+    /// The OpenVGDB title, or the file name for untagged games.
+    pub fn title(&self) -> &str {
+        if let Some(metadata) = &self.metadata {
+            metadata.title.as_str()
+        } else {
+            self.filename.as_str()
+        }
+    }
+}
+
+#[derive(Serialize)]
 pub struct GameMetadata {
-    pub release_id: i64,
+    /// OpenVGDB ROM id, for games identified through it.
+    pub release_id: Option<i64>,
     pub title: String,
-    pub cover_url: String,
+    pub cover_url: Option<String>,
+    /// Local cover image, used instead of `cover_url`.
+    pub cover_path: Option<PathBuf>,
+    /// OpenVGDB region name, e.g. `USA` or `Japan`.
+    pub region: Option<String>,
 }
 
+#[derive(Clone)]
 pub struct System {
     pub id: i64,
     pub core_path: PathBuf,
+    /// Library name and version reported by the core.
+    pub core_name: String,
+    pub core_version: String,
     pub name: String,
+    /// Name to show instead of `name`, from the config.
+    pub display_name: Option<String>,
     pub extensions: Vec<String>,
+    pub hash: HashAlgorithm,
+    pub logo: Option<PathBuf>,
+    pub rotation: Rotation,
+    /// Position in the config's system list. Systems listed first win when
+    /// several share an extension.
+    pub priority: usize,
+}
+
+impl System {
+    /// Name shown to the user.
+    pub fn display_name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.name)
+    }
 }
 
 pub struct GameDb {
     systems: HashMap<i64, System>,
     games: HashMap<i64, Game>,
-    untagged_games: Vec<Game>,
+    untagged_games: HashMap<usize, Game>,
+    /// Untagged games get ids that are never reused, so a [`GameId`] stays
+    /// valid while other games are added and removed.
+    next_untagged_id: usize,
+    /// Every path holding each ROM, by system and hash. The first one is the
+    /// game's `rom_path`, the rest are copies in other ROM directories that
+    /// take its place if it's removed.
+    rom_paths: HashMap<(i64, String), Vec<PathBuf>>,
+    dat: DatIndex,
+    /// Leave out games that don't match a good dump in the DATs.
+    verified_only: bool,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum GameId {
     Tagged(i64),
     Untagged(usize),
 }
 
+/// Per-ROM outcome of a library scan.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ScanEvent {
+    /// The ROM was identified in OpenVGDB.
+    Found { path: PathBuf },
+    /// The ROM wasn't in OpenVGDB, but its extension matched a system.
+    Untagged { path: PathBuf },
+    /// The ROM couldn't be matched to any system.
+    Failed { path: PathBuf },
+    /// The ROM couldn't be hashed.
+    HashError { path: PathBuf, error: String },
+}
+
+/// How far along the hashing phase of a scan is. Updated from the hashing
+/// threads, so it can be read while the scan runs.
+#[derive(Default, Debug)]
+pub struct ScanProgress {
+    /// Number of ROMs found. Zero until the ROM directory has been walked.
+    pub total: AtomicUsize,
+    pub hashed: AtomicUsize,
+}
+
+/// Receives scan events while [`GameDb::load_with_subscriber`] runs.
+pub trait ScanSubscriber {
+    fn event(&mut self, event: ScanEvent);
+}
+
+/// Default subscriber, which just logs every event.
+pub struct LogSubscriber;
+
+impl ScanSubscriber for LogSubscriber {
+    fn event(&mut self, event: ScanEvent) {
+        match event {
+            ScanEvent::Found { path } => log::info!("ROM Found {:?}", path),
+            ScanEvent::Untagged { path } => {
+                log::warn!("ROM Failed (extension fallback) {:?}", path)
+            }
+            ScanEvent::Failed { path } => log::error!("ROM Failed {:?}", path),
+            ScanEvent::HashError { path, error } => {
+                log::error!("ROM Hash error {:?}: {}", path, error)
+            }
+        }
+    }
+}
+
+impl ScanSubscriber for mpsc::Sender<ScanEvent> {
+    fn event(&mut self, event: ScanEvent) {
+        // The receiver going away shouldn't stop the scan
+        let _ = self.send(event);
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, sqlx::FromRow)]
 #[sqlx(rename_all = "camelCase")]
 struct OpenVgdbRom {
@@ -60,6 +183,7 @@ struct OpenVgdbRelease {
     release_date: String,
     release_reference_url: String,
     release_reference_image_url: String,
+    region_name: Option<String>,
 }
 
 #[derive(Clone, PartialEq, Eq, sqlx::FromRow)]
@@ -71,13 +195,28 @@ struct OpenVgdbSystem {
 }
 
 impl GameDb {
-    pub async fn load(cache: &mut Cache, config: &Config) -> Result<Self> {
-        let mut games = HashMap::new();
+    pub async fn load(cache: &Cache, config: &Config) -> Result<Self> {
+        Self::load_with_subscriber(cache, config, &mut LogSubscriber, &ScanProgress::default())
+            .await
+    }
+
+    /// Same as [`GameDb::load`], but reports the outcome for each ROM to
+    /// `subscriber` instead of logging it, and hashing progress to `progress`.
+    pub async fn load_with_subscriber<S>(
+        cache: &Cache,
+        config: &Config,
+        subscriber: &mut S,
+        progress: &ScanProgress,
+    ) -> Result<Self>
+    where
+        S: ScanSubscriber,
+    {
+        let games = HashMap::new();
         let mut systems = HashMap::new();
-        let mut untagged_games = Vec::new();
+        let untagged_games = HashMap::new();
 
         // TODO: download openvgdb
-        let openvgdb = sqlx::SqlitePool::connect("openvgdb.sqlite").await?;
+        let openvgdb = connect_openvgdb(&config.openvgdb_path).await?;
         let mut conn = openvgdb.acquire().await?;
 
         let cores_dir = fs::read_dir(&config.core_path)
@@ -87,25 +226,25 @@ impl GameDb {
             .map(|core| core.path());
 
         'cores: for core_path in cores_dir {
-            let (library_name, _extensions): (String, Vec<String>) = {
+            let (library_name, library_version, _extensions): (String, String, Vec<String>) = {
                 let system_info = Emulator::create_for_system_info(&core_path);
                 let string = system_info.extensions.to_str().unwrap().to_string();
                 (
                     system_info.library_name.to_str().unwrap().to_string(),
+                    system_info.library_version.to_str().unwrap().to_string(),
                     string.split('|').map(String::from).collect(),
                 )
             };
 
-            let mut system_iter = config.system.iter();
+            let preconf_system = config
+                .system
+                .iter()
+                .enumerate()
+                .find(|(_, sys)| sys.lib == library_name);
 
-            let preconf_system = loop {
-                if let Some(sys) = system_iter.next() {
-                    if sys.lib == library_name {
-                        break sys;
-                    } else {
-                        continue;
-                    }
-                } else {
+            let (priority, preconf_system) = match preconf_system {
+                Some(found) => found,
+                None => {
                     log::error!(
                         "Couldn't find system for core library name: {:?}",
                         &library_name
@@ -114,14 +253,25 @@ impl GameDb {
                 }
             };
 
+            if config
+                .hidden_systems
+                .iter()
+                .any(|hidden| *hidden == preconf_system.name || *hidden == library_name)
+            {
+                log::info!("Skipping hidden system '{}'", preconf_system.name);
+                continue;
+            }
+
             // Insert system if not yet in DB
             if let Ok(openvgdb_system) =
                 get_system_with_short_name(&mut conn, &preconf_system.name).await
             {
                 log::info!(
-                    "Inserted system '{}' for extensions: {:?}",
+                    "Inserted system '{}' for extensions: {:?} (core {} {})",
                     openvgdb_system.system_short_name,
-                    preconf_system.ext
+                    preconf_system.ext,
+                    library_name,
+                    library_version
                 );
 
                 systems.insert(
@@ -129,8 +279,15 @@ impl GameDb {
                     System {
                         id: openvgdb_system.system_id,
                         core_path: core_path.clone(),
+                        core_name: library_name.clone(),
+                        core_version: library_version.clone(),
                         name: openvgdb_system.system_short_name,
+                        display_name: preconf_system.display_name.clone(),
                         extensions: preconf_system.ext.clone(),
+                        hash: preconf_system.hash,
+                        logo: preconf_system.logo.clone(),
+                        rotation: preconf_system.rotation,
+                        priority,
                     },
                 );
             }
@@ -141,116 +298,99 @@ impl GameDb {
                     System {
                         id: system.id,
                         core_path: core_path.clone(),
+                        core_name: library_name.clone(),
+                        core_version: library_version.clone(),
                         name: system.name.clone(),
+                        display_name: preconf_system.display_name.clone(),
                         extensions: preconf_system.ext.clone(),
+                        hash: preconf_system.hash,
+                        logo: preconf_system.logo.clone(),
+                        rotation: preconf_system.rotation,
+                        priority,
                     },
                 );
             }
         }
 
-        let convert = |o: &OsStr| o.to_string_lossy().to_string();
-        let find_system_id_for_extension = |ext_a: &str| {
-            systems.iter().find_map(|(id, system)| {
-                let ext_a = ext_a.to_lowercase();
-
-                system
-                    .extensions
-                    .iter()
-                    .find(|ext_b| ext_a == ext_b.as_str())
-                    .map(|_| *id)
-            })
+        let mut game_db = GameDb {
+            systems,
+            games,
+            untagged_games,
+            next_untagged_id: 0,
+            rom_paths: HashMap::new(),
+            dat: DatIndex::load(&config.dat_files),
+            verified_only: config.verified_only,
         };
 
-        for (rom_path, name) in walkdir::WalkDir::new(&config.rom_path)
-            .into_iter()
-            .filter_map(|rom| rom.ok())
-            .filter(|rom| rom.file_type().is_file())
-            .filter_map(|rom| {
-                let path = rom.path().to_path_buf();
-                let name = path.file_name()?.to_owned();
-                Some((path, name))
-            })
-        {
-            let filename = convert(&name);
-            let extension = convert(rom_path.extension().unwrap());
-            let sha1 = match cache
-                .get_or_insert_rom_hash(rom_path.to_str().unwrap(), |_| hash_rom(&rom_path))
-            {
-                Ok(sha1) => sha1,
+        // Games from RetroArch playlists are taken as they are, without
+        // looking them up in OpenVGDB
+        let mut playlist_roms = HashSet::new();
+        let mut gamelists = GamelistIndex::default();
+
+        for playlist_path in &config.playlists {
+            let items = match playlist::load_playlist(playlist_path) {
+                Ok(items) => items,
                 Err(e) => {
-                    error!("ROM Hash error: {}", e);
+                    log::error!("Couldn't load playlist {:?}: {:#}", playlist_path, e);
                     continue;
                 }
             };
 
-            if let Ok(openvgdb_rom) = get_rom_with_sha1(&mut conn, &sha1).await {
-                log::info!("ROM Found '{}'", name.to_str().unwrap());
-                let openvgdb_release = if let Ok(release) =
-                    get_release_with_rom_id(&mut conn, openvgdb_rom.rom_id).await
-                {
-                    release
-                } else {
-                    continue;
-                };
-
-                let metadata = Some(GameMetadata {
-                    release_id: openvgdb_rom.rom_id,
-                    title: openvgdb_release.release_title_name,
-                    cover_url: openvgdb_release.release_cover_front,
-                });
-
-                if !systems.contains_key(&openvgdb_rom.system_id) {
+            for item in items {
+                // The same file can be reached through different paths
+                if !playlist_roms.insert(canonical_path(&item.path)) {
                     continue;
                 }
 
-                games.insert(
-                    openvgdb_rom.rom_id,
-                    Game {
-                        system_id: openvgdb_rom.system_id,
-                        sha1,
-                        metadata,
-                        filename,
-                        extension,
-                        rom_path,
-                        color: Color::from_rgba(
-                            rand::gen_range(0u8, 255u8),
-                            rand::gen_range(0u8, 255u8),
-                            rand::gen_range(0u8, 255u8),
-                            255,
-                        ),
-                    },
-                );
-            } else if let Some(system_id) = find_system_id_for_extension(&extension) {
-                // Separate games into games with metadata and untagged games
-                log::warn!(
-                    "ROM Failed (extension fallback) '{}'",
-                    name.to_str().unwrap(),
-                );
+                if let Some(game) = playlist_game(cache, &game_db.systems, item, subscriber) {
+                    game_db.insert_game(None, game);
+                }
+            }
+        }
 
-                untagged_games.push(Game {
-                    system_id,
-                    sha1,
-                    metadata: None,
-                    filename,
-                    extension,
-                    rom_path,
-                    color: Color::from_rgba(
-                        rand::gen_range(0u8, 255u8),
-                        rand::gen_range(0u8, 255u8),
-                        rand::gen_range(0u8, 255u8),
-                        255,
-                    ),
-                });
-            } else {
-                log::error!("ROM Failed '{}'", name.to_str().unwrap());
-            };
+        // walkdir reports symlink cycles as errors, which are skipped
+        let mut rom_paths: Vec<_> = config
+            .rom_path
+            .iter()
+            .flat_map(|dir| walkdir::WalkDir::new(dir).follow_links(config.follow_symlinks))
+            .filter_map(|rom| rom.ok())
+            .filter(|rom| rom.file_type().is_file())
+            .map(|rom| rom.path().to_path_buf())
+            .filter(|rom_path| {
+                playlist_roms.is_empty() || !playlist_roms.contains(&canonical_path(rom_path))
+            })
+            .collect();
+
+        remove_cue_tracks(&mut rom_paths);
+
+        // Hashing is by far the slowest part, so do it for all ROMs in
+        // parallel first. The lookups below then just hit the cache.
+        progress.total.store(rom_paths.len(), Ordering::Relaxed);
+
+        rom_paths.par_iter().for_each(|rom_path| {
+            let algorithm = rom_hash_algorithm(&game_db.systems, rom_path);
+            // Errors are reported when identifying the ROM
+            let _ = cached_rom_hash(cache, rom_path, algorithm);
+            progress.hashed.fetch_add(1, Ordering::Relaxed);
+        });
+
+        for rom_path in rom_paths {
+            if let Some((rom_id, game)) = identify_rom(
+                &mut conn,
+                cache,
+                &game_db.systems,
+                &mut gamelists,
+                &config.rom_path,
+                rom_path,
+                subscriber,
+            )
+            .await
+            {
+                game_db.insert_game(rom_id, game);
+            }
         }
 
-        Ok(GameDb {
-            systems,
-            games,
-            untagged_games,
-        })
+        Ok(game_db)
     }
 
     pub fn systems(&self) -> &HashMap<i64, System> {
@@ -266,8 +406,7 @@ impl GameDb {
         let untagged_iter = self
             .untagged_games
             .iter()
-            .enumerate()
-            .map(|(idx, game)| (GameId::Untagged(idx), game));
+            .map(|(id, game)| (GameId::Untagged(*id), game));
 
         games_iter.chain(untagged_iter)
     }
@@ -279,22 +418,382 @@ impl GameDb {
     pub fn get_game(&self, id: GameId) -> &Game {
         match id {
             GameId::Tagged(id) => &self.games[&id],
-            GameId::Untagged(idx) => &self.untagged_games[idx],
+            GameId::Untagged(id) => &self.untagged_games[&id],
         }
     }
 
     pub fn get_system(&self, id: i64) -> &System {
         &self.systems[&id]
     }
+
+    /// Adds a game, either under its OpenVGDB ROM id or as an untagged game.
+    /// The same ROM may be in more than one ROM directory, so copies of a
+    /// game that's already there are only remembered in case it's removed.
+    pub fn insert_game(&mut self, rom_id: Option<i64>, mut game: Game) {
+        let paths = self
+            .rom_paths
+            .entry((game.system_id, game.hash.clone()))
+            .or_default();
+        let duplicate = !paths.is_empty();
+        paths.push(game.rom_path.clone());
+
+        if duplicate {
+            log::info!("Skipping duplicate ROM {:?}", game.rom_path);
+            return;
+        }
+
+        let algorithm = self.systems.get(&game.system_id).map(|system| system.hash);
+        if let Some(algorithm) = algorithm {
+            game.verification = self.dat.verify(algorithm, &game.hash);
+        }
+
+        // DATs list hashes, which disc serials never match
+        let verifiable = algorithm != Some(HashAlgorithm::Serial);
+
+        if self.verified_only
+            && verifiable
+            && !self.dat.is_empty()
+            && game.verification != Verification::Verified
+        {
+            log::info!("Skipping unverified ROM {:?}", game.rom_path);
+            return;
+        }
+
+        match rom_id {
+            Some(rom_id) => {
+                self.games.insert(rom_id, game);
+            }
+            None => {
+                self.untagged_games.insert(self.next_untagged_id, game);
+                self.next_untagged_id += 1;
+            }
+        }
+    }
+
+    /// Forgets every ROM at `path`, or inside it if it's a directory. Games
+    /// with a copy left somewhere else switch to it, the rest are removed.
+    /// Returns whether any game was removed.
+    pub fn remove_rom(&mut self, path: &Path) -> bool {
+        for paths in self.rom_paths.values_mut() {
+            paths.retain(|rom_path| !rom_path.starts_with(path));
+        }
+        self.rom_paths.retain(|_, paths| !paths.is_empty());
+
+        let previous_len = self.games.len() + self.untagged_games.len();
+        let rom_paths = &self.rom_paths;
+        let keep = |game: &mut Game| {
+            if !game.rom_path.starts_with(path) {
+                return true;
+            }
+
+            match rom_paths.get(&(game.system_id, game.hash.clone())) {
+                Some(paths) => {
+                    let copy = &paths[0];
+                    log::info!("Using copy {:?} of removed ROM {:?}", copy, game.rom_path);
+                    game.filename = copy.file_name().map(convert).unwrap_or_default();
+                    game.extension = copy.extension().map(convert).unwrap_or_default();
+                    game.rom_path = copy.clone();
+                    true
+                }
+                None => false,
+            }
+        };
+
+        self.games.retain(|_, game| keep(game));
+        self.untagged_games.retain(|_, game| keep(game));
+
+        previous_len != self.games.len() + self.untagged_games.len()
+    }
 }
 
-async fn get_rom_with_sha1(
+/// Drops the tracks referenced by .cue sheets in `rom_paths`. They're
+/// launched through the sheet, so they shouldn't show up as games of their
+/// own.
+pub fn remove_cue_tracks(rom_paths: &mut Vec<PathBuf>) {
+    let cue_tracks: HashSet<_> = rom_paths
+        .iter()
+        .filter(|rom_path| rom_path.extension().map_or(false, |ext| ext == "cue"))
+        .filter_map(|cue_path| cue::cue_files(cue_path).ok())
+        .flatten()
+        .collect();
+    rom_paths.retain(|rom_path| !cue_tracks.contains(rom_path));
+}
+
+/// Opens the OpenVGDB database read-only.
+pub async fn connect_openvgdb(path: &Path) -> Result<sqlx::SqlitePool> {
+    let options = sqlx::sqlite::SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true);
+
+    sqlx::SqlitePool::connect_with(options)
+        .await
+        .with_context(|| format!("opening OpenVGDB at {:?}", path))
+}
+
+/// Hashes a ROM and looks it up in OpenVGDB. Returns the game along with its
+/// OpenVGDB ROM id, or no id if it was only matched by extension. Games
+/// OpenVGDB doesn't know take their metadata from EmulationStation gamelists
+/// if there is any.
+pub async fn identify_rom<S>(
     conn: &mut SqliteConnection,
-    sha1_hex: &str,
+    cache: &Cache,
+    systems: &HashMap<i64, System>,
+    gamelists: &mut GamelistIndex,
+    rom_dirs: &[PathBuf],
+    rom_path: PathBuf,
+    subscriber: &mut S,
+) -> Option<(Option<i64>, Game)>
+where
+    S: ScanSubscriber,
+{
+    let filename = convert(rom_path.file_name()?);
+    let extension = convert(rom_path.extension()?);
+    let algorithm = rom_hash_algorithm(systems, &rom_path);
+
+    let hash = match cached_rom_hash(cache, &rom_path, algorithm) {
+        Ok(hash) => hash,
+        Err(e) => {
+            subscriber.event(ScanEvent::HashError {
+                path: rom_path,
+                error: e.to_string(),
+            });
+            return None;
+        }
+    };
+
+    if let Ok(openvgdb_rom) = get_rom_with_hash(conn, algorithm, &hash).await {
+        let openvgdb_release = get_release_with_rom_id(conn, openvgdb_rom.rom_id)
+            .await
+            .ok()?;
+
+        let metadata = Some(GameMetadata {
+            release_id: Some(openvgdb_rom.rom_id),
+            title: openvgdb_release.release_title_name,
+            cover_url: Some(openvgdb_release.release_cover_front),
+            cover_path: None,
+            region: openvgdb_release.region_name,
+        });
+
+        if !systems.contains_key(&openvgdb_rom.system_id) {
+            return None;
+        }
+
+        subscriber.event(ScanEvent::Found {
+            path: rom_path.clone(),
+        });
+
+        Some((
+            Some(openvgdb_rom.rom_id),
+            Game {
+                system_id: openvgdb_rom.system_id,
+                hash,
+                metadata,
+                filename,
+                extension,
+                rom_path,
+                verification: Verification::default(),
+                color: random_color(),
+            },
+        ))
+    } else if let Some(system) = find_system_for_rom(systems, &rom_path) {
+        // Separate games into games with metadata and untagged games
+        subscriber.event(ScanEvent::Untagged {
+            path: rom_path.clone(),
+        });
+
+        let metadata = gamelists.metadata_for(&rom_path, rom_dirs, &system.name);
+
+        Some((
+            None,
+            Game {
+                system_id: system.id,
+                hash,
+                metadata,
+                filename,
+                extension,
+                rom_path,
+                verification: Verification::default(),
+                color: random_color(),
+            },
+        ))
+    } else {
+        subscriber.event(ScanEvent::Failed { path: rom_path });
+        None
+    }
+}
+
+/// Builds a game from a playlist entry. The system is the one whose core the
+/// playlist launches the game with, falling back to the extension.
+fn playlist_game<S>(
+    cache: &Cache,
+    systems: &HashMap<i64, System>,
+    item: PlaylistItem,
+    subscriber: &mut S,
+) -> Option<Game>
+where
+    S: ScanSubscriber,
+{
+    let filename = convert(item.path.file_name()?);
+    let extension = convert(item.path.extension().unwrap_or_default());
+    let core_filename = Path::new(&item.core_path).file_name();
+
+    let system = systems
+        .values()
+        .find(|system| core_filename.is_some() && system.core_path.file_name() == core_filename)
+        .or_else(|| find_system_for_rom(systems, &item.path));
+
+    let system = match system {
+        Some(system) => system,
+        None => {
+            subscriber.event(ScanEvent::Failed { path: item.path });
+            return None;
+        }
+    };
+
+    let hash = match cached_rom_hash(cache, &item.path, system.hash) {
+        Ok(hash) => hash,
+        Err(e) => {
+            subscriber.event(ScanEvent::HashError {
+                path: item.path,
+                error: e.to_string(),
+            });
+            return None;
+        }
+    };
+
+    subscriber.event(ScanEvent::Untagged {
+        path: item.path.clone(),
+    });
+
+    Some(Game {
+        system_id: system.id,
+        hash,
+        metadata: Some(GameMetadata {
+            release_id: None,
+            title: item.label,
+            cover_url: None,
+            cover_path: None,
+            region: None,
+        }),
+        filename,
+        extension,
+        rom_path: item.path,
+        verification: Verification::default(),
+        color: random_color(),
+    })
+}
+
+/// The hash algorithm depends on the system, which before identifying the ROM
+/// can only be guessed from the extension.
+fn rom_hash_algorithm(systems: &HashMap<i64, System>, rom_path: &Path) -> HashAlgorithm {
+    find_system_for_rom(systems, rom_path)
+        .map(|system| system.hash)
+        .unwrap_or_default()
+}
+
+/// Hashes a ROM, reusing the cached hash if there is one.
+fn cached_rom_hash(
+    cache: &Cache,
+    rom_path: &Path,
+    algorithm: HashAlgorithm,
+) -> anyhow::Result<String> {
+    let cache_key = rom_cache_key(rom_path, algorithm)?;
+    cache.get_or_insert_rom_hash(&cache_key, |_| hash_rom(rom_path, algorithm))
+}
+
+/// Drops the ROM's cached hash, so it's hashed again the next time it's
+/// identified. For ROMs that were replaced by a different file.
+pub fn forget_rom_hash(
+    cache: &Cache,
+    systems: &HashMap<i64, System>,
+    rom_path: &Path,
+) -> anyhow::Result<()> {
+    let algorithm = rom_hash_algorithm(systems, rom_path);
+    cache.remove_rom_hash(&rom_cache_key(rom_path, algorithm)?)
+}
+
+/// Key a ROM's hash is cached under.
+fn rom_cache_key(rom_path: &Path, algorithm: HashAlgorithm) -> anyhow::Result<String> {
+    // ZIP archives are cached as `archive_path#size:modified`, so a changed
+    // archive is hashed again without having to open it to find out
+    let mut path_key = rom_path.to_string_lossy().to_string();
+    if rom_path.extension().map_or(false, |ext| ext == "zip") {
+        let metadata = fs::metadata(rom_path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        path_key = format!("{}#{}:{}", path_key, metadata.len(), modified);
+    }
+
+    // SHA-1 hashes are cached by path alone, for compatibility with older
+    // caches.
+    Ok(match algorithm {
+        HashAlgorithm::Sha1 => path_key,
+        algorithm => format!("{:?}:{}", algorithm, path_key),
+    })
+}
+
+/// The system a ROM belongs to by its extension. When several systems share
+/// it (e.g. `.cue` for PSX and Sega CD), the one named after a directory the
+/// ROM is in wins, then the one listed first in the config.
+fn find_system_for_rom<'a>(
+    systems: &'a HashMap<i64, System>,
+    rom_path: &Path,
+) -> Option<&'a System> {
+    let extension = rom_path.extension()?.to_string_lossy().to_lowercase();
+    let dirs: Vec<String> = rom_path
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .map(|dir| dir.as_os_str().to_string_lossy().to_lowercase())
+        .collect();
+    let in_dir = |system: &System| {
+        dirs.iter().any(|dir| {
+            *dir == system.name.to_lowercase()
+                || system
+                    .display_name
+                    .as_ref()
+                    .map_or(false, |name| *dir == name.to_lowercase())
+        })
+    };
+
+    systems
+        .values()
+        .filter(|system| system.extensions.iter().any(|ext| extension == *ext))
+        .min_by_key(|system| (!in_dir(system), system.priority))
+}
+
+/// The absolute path with symlinks resolved, for comparing paths that may
+/// have been written differently. Paths that don't exist are left as they are.
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn convert(o: &OsStr) -> String {
+    o.to_string_lossy().to_string()
+}
+
+fn random_color() -> Color {
+    Color::from_rgba(
+        rand::gen_range(0u8, 255u8),
+        rand::gen_range(0u8, 255u8),
+        rand::gen_range(0u8, 255u8),
+        255,
+    )
+}
+
+async fn get_rom_with_hash(
+    conn: &mut SqliteConnection,
+    algorithm: HashAlgorithm,
+    hash_hex: &str,
 ) -> Result<OpenVgdbRom, sqlx::Error> {
-    sqlx::query_as!(
-        OpenVgdbRom,
-        r#"
+    match algorithm {
+        HashAlgorithm::Sha1 => {
+            sqlx::query_as!(
+                OpenVgdbRom,
+                r#"
                     SELECT 
                         romID as "rom_id!: _", 
                         romFileName as "rom_file_name!: _", 
@@ -303,10 +802,72 @@ async fn get_rom_with_sha1(
                     FROM ROMs 
                     WHERE romHashSHA1 = $1
                     "#,
-        sha1_hex,
-    )
-    .fetch_one(conn)
-    .await
+                hash_hex,
+            )
+            .fetch_one(conn)
+            .await
+        }
+        HashAlgorithm::Crc32 => {
+            sqlx::query_as!(
+                OpenVgdbRom,
+                r#"
+                    SELECT 
+                        romID as "rom_id!: _", 
+                        romFileName as "rom_file_name!: _", 
+                        romExtensionlessFileName as "rom_extensionless_file_name!: _" ,
+                        systemID as "system_id!: _"
+                    FROM ROMs 
+                    WHERE romHashCRC = $1
+                    "#,
+                hash_hex,
+            )
+            .fetch_one(conn)
+            .await
+        }
+        HashAlgorithm::Md5 => {
+            sqlx::query_as!(
+                OpenVgdbRom,
+                r#"
+                    SELECT 
+                        romID as "rom_id!: _", 
+                        romFileName as "rom_file_name!: _", 
+                        romExtensionlessFileName as "rom_extensionless_file_name!: _" ,
+                        systemID as "system_id!: _"
+                    FROM ROMs 
+                    WHERE romHashMD5 = $1
+                    "#,
+                hash_hex,
+            )
+            .fetch_one(conn)
+            .await
+        }
+        HashAlgorithm::Serial => {
+            let serial = hex_to_string(hash_hex);
+            sqlx::query_as!(
+                OpenVgdbRom,
+                r#"
+                    SELECT 
+                        romID as "rom_id!: _", 
+                        romFileName as "rom_file_name!: _", 
+                        romExtensionlessFileName as "rom_extensionless_file_name!: _" ,
+                        systemID as "system_id!: _"
+                    FROM ROMs 
+                    WHERE romSerial = $1
+                    "#,
+                serial,
+            )
+            .fetch_one(conn)
+            .await
+        }
+    }
+}
+
+/// Decodes a serial that went through [`bytes_to_hex`] for caching.
+fn hex_to_string(hex: &str) -> String {
+    let bytes: Vec<u8> = (0..hex.len() / 2)
+        .filter_map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok())
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
 }
 
 async fn get_release_with_rom_id(
@@ -321,9 +882,12 @@ async fn get_release_with_rom_id(
             releaseCoverFront as "release_cover_front!: _",
             releaseDate as "release_date!: _",
             releaseReferenceURL as "release_reference_url!: _",
-            releaseReferenceImageURL as "release_reference_image_url!: _"
+            releaseReferenceImageURL as "release_reference_image_url!: _",
+            regionName as "region_name?: _"
         FROM RELEASES 
-        WHERE romID = $1
+        LEFT JOIN ROMs ON ROMs.romID = RELEASES.romID
+        LEFT JOIN REGIONS ON REGIONS.regionID = ROMs.regionID
+        WHERE RELEASES.romID = $1
         ORDER BY releaseDate
         "#,
         rom_id,
@@ -351,3 +915,42 @@ async fn get_system_with_short_name(
     .fetch_one(conn)
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_sha1_hashes_by_path_alone() {
+        let key = rom_cache_key(Path::new("/roms/snes/game.sfc"), HashAlgorithm::Sha1).unwrap();
+        assert_eq!(key, "/roms/snes/game.sfc");
+    }
+
+    #[test]
+    fn keys_other_hashes_by_algorithm_and_path() {
+        let key = rom_cache_key(Path::new("/roms/lynx/game.lnx"), HashAlgorithm::Crc32).unwrap();
+        assert_eq!(key, "Crc32:/roms/lynx/game.lnx");
+    }
+
+    #[test]
+    fn keys_archives_by_size_and_modification_time() {
+        let path = std::env::temp_dir().join(format!("retroarcade-{}-key.zip", std::process::id()));
+        fs::write(&path, [0u8; 42]).unwrap();
+        let key = rom_cache_key(&path, HashAlgorithm::Md5);
+        let modified = fs::metadata(&path)
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            key.unwrap(),
+            format!("Md5:{}#42:{}", path.to_string_lossy(), modified)
+        );
+        // Archives that can't be read have no key
+        assert!(rom_cache_key(&path, HashAlgorithm::Sha1).is_err());
+    }
+}
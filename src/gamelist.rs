@@ -0,0 +1,140 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::game_db::GameMetadata;
+
+/// An entry in an EmulationStation `gamelist.xml`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GamelistEntry {
+    pub name: String,
+    pub image: Option<PathBuf>,
+}
+
+/// Lazily loads EmulationStation `gamelist.xml` files, from the ROM
+/// directories or from `~/.emulationstation/gamelists/<system>/`.
+#[derive(Default)]
+pub struct GamelistIndex {
+    /// Entries by absolute ROM path, per gamelist file and the directory its
+    /// paths are relative to. Missing gamelists map to an empty list.
+    gamelists: HashMap<(PathBuf, PathBuf), HashMap<PathBuf, GamelistEntry>>,
+}
+
+impl GamelistIndex {
+    /// Looks for the ROM in the gamelists of its directory and every parent
+    /// up to the ROM directory it's in, then in EmulationStation's own
+    /// gamelist for `system_name`.
+    pub fn metadata_for(
+        &mut self,
+        rom_path: &Path,
+        rom_dirs: &[PathBuf],
+        system_name: &str,
+    ) -> Option<GameMetadata> {
+        let rom_dir = rom_dirs.iter().find(|dir| rom_path.starts_with(dir));
+        let mut gamelists: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+        for dir in rom_path.ancestors().skip(1) {
+            gamelists.push((dir.join("gamelist.xml"), dir.to_path_buf()));
+
+            if rom_dir.map_or(true, |rom_dir| dir == rom_dir) {
+                break;
+            }
+        }
+
+        // Paths in these are relative to the system's ROM directory, which
+        // EmulationStation names after the system
+        if let Some(home) = env::var_os("HOME") {
+            let system = system_name.to_lowercase();
+            let gamelist_path = Path::new(&home)
+                .join(".emulationstation/gamelists")
+                .join(&system)
+                .join("gamelist.xml");
+            let base = rom_path
+                .ancestors()
+                .find(|dir| {
+                    dir.file_name().map_or(false, |name| {
+                        name.to_string_lossy().to_lowercase() == system
+                    })
+                })
+                .or(rom_dir.map(PathBuf::as_path));
+
+            if let Some(base) = base {
+                gamelists.push((gamelist_path, base.to_path_buf()));
+            }
+        }
+
+        let rom_path = normalize(rom_path);
+        let entry = gamelists.into_iter().find_map(|key| {
+            self.gamelists
+                .entry(key)
+                .or_insert_with_key(|(gamelist_path, base)| load_gamelist(gamelist_path, base))
+                .get(&rom_path)
+                .cloned()
+        })?;
+
+        Some(GameMetadata {
+            release_id: None,
+            title: entry.name,
+            cover_url: None,
+            cover_path: entry.image,
+            region: None,
+        })
+    }
+}
+
+/// Reads a gamelist, or nothing if there isn't one at `path`.
+fn load_gamelist(path: &Path, base: &Path) -> HashMap<PathBuf, GamelistEntry> {
+    if !path.is_file() {
+        return HashMap::new();
+    }
+
+    match parse_gamelist(path, base) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("Couldn't parse {:?}: {}", path, e);
+            HashMap::new()
+        }
+    }
+}
+
+fn parse_gamelist(path: &Path, base: &Path) -> Result<HashMap<PathBuf, GamelistEntry>> {
+    let contents = fs::read_to_string(path)?;
+    let document = roxmltree::Document::parse(&contents)?;
+
+    let child_text = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|child| child.has_tag_name(name))
+            .and_then(|child| child.text())
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    };
+
+    let entries = document
+        .root_element()
+        .children()
+        .filter(|node| node.has_tag_name("game"))
+        .filter_map(|game| {
+            // Paths are relative to `base`
+            let rom_path = normalize(&base.join(child_text(game, "path")?));
+            let entry = GamelistEntry {
+                name: child_text(game, "name")?,
+                image: child_text(game, "image").map(|image| normalize(&base.join(image))),
+            };
+            Some((rom_path, entry))
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+/// Drops `.` components so `roms/./game.sfc` and `roms/game.sfc` compare
+/// equal.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != std::path::Component::CurDir)
+        .collect()
+}
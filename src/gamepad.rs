@@ -2,7 +2,9 @@ use gilrs::{Axis, Button, Gamepad};
 use macroquad::prelude::*;
 use retro_rs::{Buttons, InputPort};
 
-pub fn update_input_port_with_gamepad(input: &mut InputPort, g: &Gamepad) {
+/// `trigger_threshold` is how far an analog L2/R2 must be pulled to count as
+/// pressed.
+pub fn update_input_port_with_gamepad(input: &mut InputPort, g: &Gamepad, trigger_threshold: f32) {
     input.buttons = Buttons::new()
         .up(g.is_pressed(Button::DPadUp))
         .down(g.is_pressed(Button::DPadDown))
@@ -14,8 +16,8 @@ pub fn update_input_port_with_gamepad(input: &mut InputPort, g: &Gamepad) {
         .y(g.is_pressed(Button::West))
         .l1(g.is_pressed(Button::LeftTrigger))
         .r1(g.is_pressed(Button::RightTrigger))
-        .l2(g.is_pressed(Button::LeftTrigger2))
-        .r2(g.is_pressed(Button::RightTrigger2))
+        .l2(trigger_value(g, Button::LeftTrigger2, Axis::LeftZ) >= trigger_threshold)
+        .r2(trigger_value(g, Button::RightTrigger2, Axis::RightZ) >= trigger_threshold)
         .l3(g.is_pressed(Button::LeftThumb))
         .r3(g.is_pressed(Button::RightThumb))
         .start(g.is_pressed(Button::Start))
@@ -66,6 +68,14 @@ pub fn update_input_port_with_keyboard(input: &mut InputPort) {
     }
 }
 
+/// How far a trigger is pulled, from 0 to 1. Depending on the pad, analog
+/// triggers show up either as a button with a value or as an axis.
+fn trigger_value(gamepad: &Gamepad, button: Button, axis: Axis) -> f32 {
+    let button_value = gamepad.button_data(button).map_or(0.0, |data| data.value());
+    let axis_value = gamepad.axis_data(axis).map_or(0.0, |data| data.value());
+    button_value.max(axis_value)
+}
+
 pub fn get_stick(gamepad: &Gamepad) -> (f32, f32) {
     let x = gamepad.axis_data(Axis::LeftStickX);
     let y = gamepad.axis_data(Axis::LeftStickY);
@@ -5,35 +5,202 @@ use std::{
 };
 
 use log::error;
+use md5::Md5;
+use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 use thiserror::Error;
+use zip::ZipArchive;
 
-pub type Sha1Hash = [u8; 20];
+use crate::cue;
 
-pub fn hash_rom<P>(rom_path: P) -> Result<Sha1Hash, RomHashError>
+/// Hash used to identify a system's ROMs in OpenVGDB.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Crc32,
+    Md5,
+    /// Not a hash: the product code from a Sega CD or Saturn disc header.
+    /// Whole-image hashes of these discs rarely match anything.
+    Serial,
+}
+
+pub fn hash_rom<P>(rom_path: P, algorithm: HashAlgorithm) -> Result<Vec<u8>, RomHashError>
 where
     P: AsRef<Path>,
 {
-    let mut file = File::open(&rom_path)?;
-    let mut hasher = Sha1::new();
+    let rom_path = rom_path.as_ref();
 
-    match rom_path.as_ref().extension().and_then(|e| e.to_str()) {
-        Some("sfc") => SnesHasher::hash(&mut file, &mut hasher),
-        Some("nes") => NesHasher::hash(&mut file, &mut hasher),
-        _ => DefaultHasher::hash(&mut file, &mut hasher),
-    }?;
+    match algorithm {
+        HashAlgorithm::Sha1 => {
+            let mut hasher = Sha1::new();
+            hash_rom_with(rom_path, &mut hasher)?;
+            Ok(hasher.finalize().to_vec())
+        }
+        HashAlgorithm::Crc32 => {
+            let mut hasher = Crc32Writer(crc32fast::Hasher::new());
+            hash_rom_with(rom_path, &mut hasher)?;
+            Ok(hasher.0.finalize().to_be_bytes().to_vec())
+        }
+        HashAlgorithm::Md5 => {
+            let mut hasher = Md5::new();
+            hash_rom_with(rom_path, &mut hasher)?;
+            Ok(hasher.finalize().to_vec())
+        }
+        HashAlgorithm::Serial => Ok(disc_serial(rom_path)?.into_bytes()),
+    }
+}
+
+/// Reads the product code (e.g. `MK-81009`) from the header of a Sega CD or
+/// Saturn disc's data track.
+fn disc_serial(rom_path: &Path) -> Result<String, RomHashError> {
+    // The data track is the first one
+    let track = if rom_path.extension().map_or(false, |ext| ext == "cue") {
+        cue::cue_files(rom_path)?
+            .into_iter()
+            .next()
+            .ok_or(RomHashError::Invalid)?
+    } else {
+        rom_path.to_path_buf()
+    };
 
-    Ok(hasher.finalize().into())
+    let mut header = [0u8; 0x200];
+    File::open(track)?.read_exact(&mut header)?;
+    header_serial(&header)
+}
+
+/// Reads the product code out of the first 0x200 bytes of a data track.
+fn header_serial(header: &[u8]) -> Result<String, RomHashError> {
+    // Raw 2352-byte sectors start with a 12-byte sync pattern and a 4-byte
+    // sector header before the data
+    const SYNC: [u8; 12] = [
+        0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0,
+    ];
+    let header = if header[..12] == SYNC {
+        &header[16..]
+    } else {
+        &header[..]
+    };
+
+    let field = |start: usize, len: usize| String::from_utf8_lossy(&header[start..start + len]);
+
+    let serial = if header.starts_with(b"SEGADISCSYSTEM") {
+        // e.g. "GM MK-4407 -00": type, product code, version. The version
+        // is separated by a space, since codes have dashes of their own.
+        let field = field(0x180, 14);
+        let code = field.get(3..).unwrap_or_default().trim();
+        code.rsplit_once(' ')
+            .filter(|(_, version)| {
+                let version = version.trim_start_matches('-');
+                !version.is_empty() && version.chars().all(|c| c.is_ascii_digit())
+            })
+            .map_or(code, |(code, _)| code)
+            .trim()
+            .to_string()
+    } else if header.starts_with(b"SEGA SEGASATURN") {
+        field(0x20, 10).trim().to_string()
+    } else {
+        return Err(RomHashError::Unsupported);
+    };
+
+    if serial.is_empty() {
+        Err(RomHashError::Invalid)
+    } else {
+        Ok(serial)
+    }
+}
+
+fn hash_rom_with(rom_path: &Path, hasher: &mut dyn Write) -> Result<(), RomHashError> {
+    let extension = rom_path.extension().and_then(|e| e.to_str());
+
+    // Disc images are identified by their first track
+    if extension == Some("cue") {
+        let first_track = cue::cue_files(rom_path)?
+            .into_iter()
+            .next()
+            .ok_or(RomHashError::Invalid)?;
+        let mut file = File::open(&first_track)?;
+        let size = file.metadata()?.len();
+        return DefaultHasher::hash(&mut file, size, hasher);
+    }
+
+    // Zipped ROMs are identified by the ROM inside, as if it were loose
+    if extension == Some("zip") {
+        let mut archive = ZipArchive::new(File::open(rom_path)?)?;
+        let index = zip_rom_entry(&mut archive)?;
+        let mut entry = archive.by_index(index)?;
+        let size = entry.size();
+        let entry_name = entry.name().to_string();
+        let entry_extension = Path::new(&entry_name).extension().and_then(|e| e.to_str());
+
+        return hash_with_extension(entry_extension, &mut entry, size, hasher);
+    }
+
+    let mut file = File::open(rom_path)?;
+    let size = file.metadata()?.len();
+    hash_with_extension(extension, &mut file, size, hasher)
+}
+
+fn hash_with_extension(
+    extension: Option<&str>,
+    rom: &mut dyn Read,
+    size: u64,
+    hasher: &mut dyn Write,
+) -> Result<(), RomHashError> {
+    match extension {
+        Some("sfc") => SnesHasher::hash(rom, size, hasher),
+        Some("nes") => NesHasher::hash(rom, size, hasher),
+        Some("lnx") => LynxHasher::hash(rom, size, hasher),
+        Some("pce") => PceHasher::hash(rom, size, hasher),
+        _ => DefaultHasher::hash(rom, size, hasher),
+    }
+}
+
+/// Index of the ROM inside a ZIP archive, taken to be its largest file.
+fn zip_rom_entry(archive: &mut ZipArchive<File>) -> Result<usize, RomHashError> {
+    let mut largest: Option<(u64, usize)> = None;
+
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+
+        if entry.is_file()
+            && largest
+                .as_ref()
+                .map_or(true, |(size, _)| entry.size() > *size)
+        {
+            largest = Some((entry.size(), i));
+        }
+    }
+
+    largest.map(|(_, index)| index).ok_or(RomHashError::Invalid)
+}
+
+/// `crc32fast` doesn't implement `Write`, which the ROM hashers feed into.
+struct Crc32Writer(crc32fast::Hasher);
+
+impl Write for Crc32Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 pub trait RomHasher {
-    fn hash(file: &mut File, hasher: &mut dyn Write) -> Result<(), RomHashError>;
+    /// Feeds the ROM's `size` bytes into `hasher`, minus any header.
+    fn hash(rom: &mut dyn Read, size: u64, hasher: &mut dyn Write) -> Result<(), RomHashError>;
 }
 
 #[derive(Error, Debug)]
 pub enum RomHashError {
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
+    #[error("ZIP error: {0}")]
+    Zip(#[from] zip::result::ZipError),
     #[error("Invalid ROM")]
     Invalid,
     #[error("Unsupported ROM format")]
@@ -43,8 +210,8 @@ pub enum RomHashError {
 pub struct DefaultHasher;
 
 impl RomHasher for DefaultHasher {
-    fn hash(file: &mut File, hasher: &mut dyn Write) -> Result<(), RomHashError> {
-        let _ = io::copy(file, hasher)?;
+    fn hash(rom: &mut dyn Read, _size: u64, hasher: &mut dyn Write) -> Result<(), RomHashError> {
+        let _ = io::copy(rom, hasher)?;
         Ok(())
     }
 }
@@ -52,14 +219,12 @@ impl RomHasher for DefaultHasher {
 pub struct SnesHasher;
 
 impl RomHasher for SnesHasher {
-    fn hash(file: &mut File, hasher: &mut dyn Write) -> Result<(), RomHashError> {
-        let size = file.metadata()?.len();
-
+    fn hash(rom: &mut dyn Read, size: u64, hasher: &mut dyn Write) -> Result<(), RomHashError> {
         if size % 1024 == 512 {
-            file.read_exact(&mut [0; 512])?;
+            rom.read_exact(&mut [0; 512])?;
         }
 
-        let _ = io::copy(file, hasher)?;
+        let _ = io::copy(rom, hasher)?;
         Ok(())
     }
 }
@@ -67,9 +232,9 @@ impl RomHasher for SnesHasher {
 pub struct NesHasher;
 
 impl RomHasher for NesHasher {
-    fn hash(file: &mut File, hasher: &mut dyn Write) -> Result<(), RomHashError> {
+    fn hash(rom: &mut dyn Read, _size: u64, hasher: &mut dyn Write) -> Result<(), RomHashError> {
         let mut header = [0u8; 16];
-        file.read_exact(&mut header)?;
+        rom.read_exact(&mut header)?;
 
         if &header[..3] != b"NES" {
             return Err(RomHashError::Invalid);
@@ -79,10 +244,40 @@ impl RomHasher for NesHasher {
 
         if has_trainer {
             let mut tmp = [0u8; 512];
-            file.read_exact(&mut tmp)?;
+            rom.read_exact(&mut tmp)?;
+        }
+
+        let _ = io::copy(rom, hasher)?;
+        Ok(())
+    }
+}
+
+pub struct LynxHasher;
+
+impl RomHasher for LynxHasher {
+    fn hash(rom: &mut dyn Read, _size: u64, hasher: &mut dyn Write) -> Result<(), RomHashError> {
+        let mut header = [0u8; 64];
+        rom.read_exact(&mut header)?;
+
+        if &header[..4] != b"LYNX" {
+            return Err(RomHashError::Invalid);
         }
 
-        let _ = io::copy(file, hasher)?;
+        let _ = io::copy(rom, hasher)?;
+        Ok(())
+    }
+}
+
+pub struct PceHasher;
+
+impl RomHasher for PceHasher {
+    fn hash(rom: &mut dyn Read, size: u64, hasher: &mut dyn Write) -> Result<(), RomHashError> {
+        // Some dumps carry a 512-byte copier header before the 8 KiB banks
+        if size % 8192 == 512 {
+            rom.read_exact(&mut [0; 512])?;
+        }
+
+        let _ = io::copy(rom, hasher)?;
         Ok(())
     }
 }
@@ -97,3 +292,87 @@ pub fn bytes_to_hex(bytes: &[u8]) -> String {
 
     hex
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First bytes of a data track, with `fields` written at their offsets.
+    fn header(fields: &[(usize, &str)]) -> Vec<u8> {
+        let mut header = vec![b' '; 0x200];
+        for (offset, field) in fields {
+            header[*offset..offset + field.len()].copy_from_slice(field.as_bytes());
+        }
+        header
+    }
+
+    #[test]
+    fn reads_sega_cd_serials() {
+        let track = header(&[(0, "SEGADISCSYSTEM"), (0x180, "GM MK-4407 -00")]);
+        assert_eq!(header_serial(&track).unwrap(), "MK-4407");
+
+        // Dashes in the product code itself are kept
+        let track = header(&[(0, "SEGADISCSYSTEM"), (0x180, "GM T-113015-00")]);
+        assert_eq!(header_serial(&track).unwrap(), "T-113015-00");
+    }
+
+    #[test]
+    fn reads_saturn_serials() {
+        let track = header(&[(0, "SEGA SEGASATURN"), (0x20, "MK-81009  ")]);
+        assert_eq!(header_serial(&track).unwrap(), "MK-81009");
+    }
+
+    #[test]
+    fn skips_raw_sector_headers() {
+        let mut raw = vec![
+            0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0,
+        ];
+        raw.extend_from_slice(&[0, 2, 0, 1]);
+        raw.extend(header(&[(0, "SEGA SEGASATURN"), (0x20, "GS-9001   ")]));
+        raw.truncate(0x200);
+
+        assert_eq!(header_serial(&raw).unwrap(), "GS-9001");
+    }
+
+    /// Runs `H` over `rom`, returning what it fed to the hasher.
+    fn hashed<H: RomHasher>(rom: &[u8]) -> Result<Vec<u8>, RomHashError> {
+        let mut hashed = Vec::new();
+        H::hash(&mut &rom[..], rom.len() as u64, &mut hashed)?;
+        Ok(hashed)
+    }
+
+    #[test]
+    fn strips_lynx_headers() {
+        let mut rom = b"LYNX".to_vec();
+        rom.resize(64, 0);
+        rom.extend_from_slice(&[1, 2, 3, 4]);
+
+        assert_eq!(hashed::<LynxHasher>(&rom).unwrap(), [1, 2, 3, 4]);
+        assert!(matches!(
+            hashed::<LynxHasher>(&[0; 128]),
+            Err(RomHashError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn strips_pce_copier_headers() {
+        let banks: Vec<u8> = (0..8192).map(|i| i as u8).collect();
+        let mut rom = vec![0xFF; 512];
+        rom.extend_from_slice(&banks);
+
+        assert_eq!(hashed::<PceHasher>(&rom).unwrap(), banks);
+        assert_eq!(hashed::<PceHasher>(&banks).unwrap(), banks);
+    }
+
+    #[test]
+    fn rejects_other_discs() {
+        assert!(matches!(
+            header_serial(&header(&[(0, "PLAYSTATION")])),
+            Err(RomHashError::Unsupported)
+        ));
+        assert!(matches!(
+            header_serial(&header(&[(0, "SEGA SEGASATURN")])),
+            Err(RomHashError::Invalid)
+        ));
+    }
+}
@@ -0,0 +1,40 @@
+use std::{path::Path, time::Instant};
+
+use anyhow::{Context, Result};
+use retro_rs::{Emulator, InputPort, RetroRsError};
+use sha1::{Digest, Sha1};
+
+use crate::{emulator::catch_panic, hash::bytes_to_hex};
+
+/// Runs `frames` frames of a game with no input, window or audio, then
+/// prints how fast it ran and a hash of the last frame. The hash only
+/// changes if the core's output does, so it can be compared across runs.
+pub fn run(core: &Path, rom: &Path, frames: u32) -> Result<()> {
+    let mut emu = catch_panic(|| Emulator::create(core, rom)).context("loading game")?;
+    let controllers = [InputPort::new(), InputPort::new()];
+
+    let start = Instant::now();
+    for _ in 0..frames {
+        catch_panic(|| emu.run(controllers)).context("running core")?;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let mut framebuffer_hash = None;
+    match emu.peek_framebuffer(|fb: &[u8]| framebuffer_hash = Some(Sha1::digest(fb).to_vec())) {
+        Err(RetroRsError::NoFramebufferError) => (),
+        Err(e) => return Err(e.into()),
+        Ok(_) => (),
+    }
+
+    println!("frames: {}", frames);
+    println!("seconds: {:.3}", elapsed);
+    println!("fps: {:.1}", frames as f64 / elapsed);
+    println!(
+        "framebuffer sha1: {}",
+        framebuffer_hash
+            .as_deref()
+            .map_or("none".to_string(), bytes_to_hex)
+    );
+
+    Ok(())
+}
@@ -1,16 +1,31 @@
 mod audio;
 mod cache;
 mod config;
+mod cue;
+mod dat;
 mod dialog;
 mod emulator;
+mod export;
 mod game_db;
+mod gamelist;
 mod gamepad;
 mod hash;
+mod headless;
 mod menu;
+mod movie;
+mod playlist;
+mod sound;
+mod watcher;
 
 use std::{
     collections::{HashMap, VecDeque},
-    path::PathBuf,
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, TryRecvError},
+        Arc,
+    },
+    thread,
+    time::Duration,
 };
 
 use dotenv::dotenv;
@@ -20,27 +35,82 @@ use macroquad::prelude::*;
 use crate::{
     cache::Cache,
     config::*,
-    dialog::{Dialog, DialogUpdate, DynamicDialog},
+    dialog::{DialogUpdate, DynamicDialog, MessageDialog},
     emulator::*,
     game_db::*,
     menu::*,
+    sound::{MenuAudio, SoundEffect},
+    watcher::RomWatcher,
 };
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     pretty_env_logger::init();
+
+    // `--headless <core> <rom> [frames]` runs a game without a window or
+    // audio and prints its speed and last frame's hash, for tests and
+    // benchmarks. It doesn't need the config or the library.
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--headless") {
+        let core = args.get(i + 1).expect("--headless requires a core path");
+        let rom = args.get(i + 2).expect("--headless requires a ROM path");
+        let frames = args
+            .get(i + 3)
+            .map(|frames| frames.parse().expect("invalid frame count"))
+            .unwrap_or(600);
+
+        headless::run(core.as_ref(), rom.as_ref(), frames).unwrap();
+        return;
+    }
+
     let config = Config::load("retroarcade.toml").unwrap();
-    let mut cache = Cache::new("cache/hashes", "cache/image").unwrap();
-    let game_db = GameDb::load(&mut cache, &config).await.unwrap();
+    let cache = Cache::new(
+        config.cache_path.join("hashes"),
+        config.cache_path.join("image"),
+    )
+    .unwrap();
+
+    // `--export-list <file>` writes the library to a JSON or CSV file and
+    // exits without opening the window
+    if let Some(i) = args.iter().position(|arg| arg == "--export-list") {
+        let path = args.get(i + 1).expect("--export-list requires a file path");
+        let game_db = GameDb::load(&cache, &config).await.unwrap();
+        export::export_game_list(&game_db, path).unwrap();
+        return;
+    }
+
+    let window_conf = Conf {
+        window_title: "RetroArcade".to_string(),
+        fullscreen: config.fullscreen,
+        window_width: config.window_width,
+        window_height: config.window_height,
+        platform: macroquad::miniquad::conf::Platform {
+            swap_interval: Some(if config.vsync { 1 } else { 0 }),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let runtime = tokio::runtime::Handle::current();
 
-    macroquad::Window::new("RetroArcade", async {
-        let result = macroquad_main(config, game_db, cache).await;
+    macroquad::Window::from_config(window_conf, async {
+        let result = macroquad_main(config, cache, runtime).await;
         result.unwrap();
     });
 }
 
-async fn macroquad_main(config: Config, game_db: GameDb, cache: Cache) -> anyhow::Result<()> {
+async fn macroquad_main(
+    mut config: Config,
+    cache: Cache,
+    runtime: tokio::runtime::Handle,
+) -> anyhow::Result<()> {
+    // Closing the window is handled in the main loop, so the running game's
+    // save RAM can be written first
+    prevent_quit();
+
+    let (game_db, cache) = load_game_db(config.clone(), cache, runtime.clone()).await?;
+
     let glowing_material = load_material(
         include_str!("shaders/glowing_vert.glsl"),
         include_str!("shaders/glowing_frag.glsl"),
@@ -54,70 +124,178 @@ async fn macroquad_main(config: Config, game_db: GameDb, cache: Cache) -> anyhow
             ..Default::default()
         },
     )?;
-    glowing_material.set_uniform("glowFrequency", 1.0f32);
-    glowing_material.set_uniform("glowIntensity", 1.0f32);
-    glowing_material.set_uniform("zoomFactor", 0.2f32);
+    let glow = &config.menu.glow;
+    glowing_material.set_uniform("glowFrequency", glow.frequency);
+    glowing_material.set_uniform("glowIntensity", glow.intensity);
+    glowing_material.set_uniform("zoomFactor", glow.zoom_factor);
+
+    if let Some(view) = load_saved_view(&config) {
+        config.menu.view = view;
+    }
 
     let max_tile_size = config.menu.max_tile_size;
+    let fullscreen = config.fullscreen;
+
+    let rom_watcher =
+        match RomWatcher::spawn(&config, game_db.systems().clone(), cache.clone(), runtime) {
+            Ok(rom_watcher) => Some(rom_watcher),
+            Err(e) => {
+                log::error!("Couldn't watch ROM directory: {}", e);
+                None
+            }
+        };
+
+    if config.prefetch_covers {
+        spawn_cover_prefetch(&game_db, cache.clone(), config.clone());
+    }
+
+    let logos = load_logos(&game_db);
+    let audio = match MenuAudio::new(&config.menu.sounds) {
+        Ok(audio) => audio,
+        Err(e) => {
+            log::error!("Couldn't load menu sounds: {:#}", e);
+            None
+        }
+    };
 
     let mut app = App {
-        state: AppState::Menu,
+        states: vec![AppState::Menu],
         menu: MenuState {
             game_db,
             config,
             cache,
             textures: HashMap::new(),
+            logos,
+            rom_watcher,
 
-            selected_game: 0,
+            order: Vec::new(),
+            order_index: HashMap::new(),
+            selected: None,
             max_tile_size,
+            scroll_offset: 0.0,
 
             glowing_material,
             time: 0.0,
+            title_time: 0.0,
+            idle_time: 0.0,
+            combos_held: (false, false),
             input: MenuInput::default(),
+            audio,
         },
         emulator: None,
         gilrs: Gilrs::new().unwrap(),
 
         dialog_queue: VecDeque::new(),
+        urgent_dialog_queue: VecDeque::new(),
         current_dialog: None,
+        current_dialog_urgent: false,
+        fullscreen,
     };
 
-    // Draw loading screen
-    draw_loading_screen();
-    next_frame().await;
+    app.menu.refresh_order();
 
-    loop {
+    // Only needed without vsync, which already paces frames
+    let min_frame_time = match app.menu.config.max_fps {
+        0 => None,
+        _ if app.menu.config.vsync => None,
+        max_fps => Some(1.0 / max_fps as f64),
+    };
+
+    let mut frame_start = get_time();
+
+    while !is_quit_requested() {
+        // Measured from the previous frame's start, so drawing and presenting
+        // it count towards the frame time too
+        if let Some(min_frame_time) = min_frame_time {
+            let remaining = min_frame_time - (get_time() - frame_start);
+
+            if remaining > 0.0 {
+                std::thread::sleep(Duration::from_secs_f64(remaining));
+            }
+        }
+
+        frame_start = get_time();
         let event = app.update();
 
         match event {
             AppEvent::Continue => (),
-            AppEvent::GoToMenu => {
-                app.state = AppState::Menu;
+            AppEvent::GoToMenu => app.go_to_menu(),
+            AppEvent::StartEmulator(game) => {
+                match EmulatorState::create(game, app.menu.config.emulator.clone()) {
+                    Ok(emulator) => {
+                        app.menu.play_sound(SoundEffect::Launch);
+                        app.menu.set_music_playing(false);
+                        app.emulator = Some(emulator);
+                        app.push_state(AppState::Emulator);
+                    }
+                    Err(e) => app.show_emulator_error(format!("{:#}", e)),
+                }
+            }
+            AppEvent::EmulatorError(error) => {
+                // The crashed core's save RAM can't be trusted, so keep the
+                // last good file instead of writing it
                 app.emulator = None;
+                app.go_to_menu();
+                app.show_emulator_error(error);
             }
-            AppEvent::StartEmulator { core, rom, save } => {
-                app.state = AppState::Emulator;
-                app.emulator = Some(EmulatorState::create(&core, &rom, save));
+            AppEvent::SetCover(screenshot) => {
+                let message = match app.menu.set_custom_cover(&screenshot) {
+                    Ok(()) => "Cover saved",
+                    Err(e) => {
+                        log::error!("Couldn't save cover: {:#}", e);
+                        "Couldn't save cover"
+                    }
+                };
+
+                if let Some(emulator) = &mut app.emulator {
+                    emulator.show_message(message, 120);
+                }
             }
             AppEvent::SpawnDialog(dialog) => {
                 app.dialog_queue.push_back(dialog);
             }
+            AppEvent::SpawnUrgentDialog(dialog) => {
+                app.spawn_urgent_dialog(dialog, true);
+            }
+            AppEvent::RunCommand(cmd) => {
+                app.write_sram();
+                run_command(&cmd);
+            }
         }
 
         app.render();
 
+        // Nothing is moving on an idle menu, so there's no need to redraw at
+        // full speed
+        if *app.state() == AppState::Menu && app.current_dialog.is_none() && app.menu.is_idle() {
+            std::thread::sleep(IDLE_FRAME_TIME);
+        }
+
         next_frame().await;
     }
+
+    app.write_sram();
+    Ok(())
 }
 
+/// Minimum frame time while the menu is idle.
+const IDLE_FRAME_TIME: Duration = Duration::from_millis(100);
+
 pub struct App {
-    pub state: AppState,
+    /// Screens on top of each other, the last one being shown. The menu is
+    /// always at the bottom.
+    pub states: Vec<AppState>,
     pub menu: MenuState,
     pub emulator: Option<EmulatorState>,
     pub gilrs: Gilrs,
 
     pub dialog_queue: VecDeque<DynamicDialog>,
+    /// Dialogs shown before anything in `dialog_queue`, e.g. errors.
+    pub urgent_dialog_queue: VecDeque<DynamicDialog>,
     pub current_dialog: Option<DynamicDialog>,
+    pub current_dialog_urgent: bool,
+
+    pub fullscreen: bool,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -129,40 +307,49 @@ pub enum AppState {
 pub enum AppEvent {
     Continue,
     GoToMenu,
-    StartEmulator {
-        core: PathBuf,
-        rom: PathBuf,
-        save: Option<Vec<u8>>,
-    },
+    StartEmulator(GameLaunch),
+    /// Saves the image as the running game's cover.
+    SetCover(Image),
     SpawnDialog(DynamicDialog),
+    /// Shows the dialog ahead of all queued ones, interrupting the current
+    /// dialog unless it is urgent too.
+    SpawnUrgentDialog(DynamicDialog),
+    /// The emulator failed and can't continue.
+    EmulatorError(String),
+    /// Runs a shell command, like the poweroff or reboot command.
+    RunCommand(String),
 }
 
 impl App {
     pub fn update(&mut self) -> AppEvent {
+        // Fullscreen toggle
+        if is_key_pressed(KeyCode::F11) {
+            self.fullscreen = !self.fullscreen;
+            set_fullscreen(self.fullscreen);
+        }
+
         // Update dialogs
         if self.current_dialog.is_none() {
-            self.current_dialog = self.dialog_queue.pop_front();
+            if let Some(dialog) = self.urgent_dialog_queue.pop_front() {
+                self.current_dialog = Some(dialog);
+                self.current_dialog_urgent = true;
+            } else {
+                self.current_dialog = self.dialog_queue.pop_front();
+                self.current_dialog_urgent = false;
+            }
         }
 
         if let Some(dialog) = &mut self.current_dialog {
-            let update = match dialog {
-                DynamicDialog::YesOrNo(dialog) => dialog.update(),
-            };
-
-            match update {
+            match dialog.update(&mut self.gilrs) {
                 DialogUpdate::Finish => {
                     let dialog = self.current_dialog.take().unwrap();
-                    let event = match dialog {
-                        DynamicDialog::YesOrNo(dialog) => dialog.produce_event(),
-                    };
-
-                    return event;
+                    return dialog.produce_event();
                 }
                 DialogUpdate::Continue => return AppEvent::Continue,
             };
         };
 
-        match self.state {
+        match self.state().clone() {
             AppState::Menu => self.menu.update(&mut self.gilrs),
             AppState::Emulator => {
                 if let Some(emulator) = &mut self.emulator {
@@ -174,8 +361,76 @@ impl App {
         }
     }
 
+    /// The screen being shown.
+    pub fn state(&self) -> &AppState {
+        self.states.last().unwrap_or(&AppState::Menu)
+    }
+
+    /// Shows `state` on top of the current screen.
+    pub fn push_state(&mut self, state: AppState) {
+        self.states.push(state);
+    }
+
+    /// Leaves the current screen for the one below it. The menu is never
+    /// left.
+    pub fn back(&mut self) {
+        if self.states.len() <= 1 {
+            return;
+        }
+
+        match self.states.pop() {
+            Some(AppState::Emulator) => {
+                self.write_sram();
+                self.emulator = None;
+            }
+            Some(AppState::Menu) | None => (),
+        }
+
+        if *self.state() == AppState::Menu {
+            self.menu.set_music_playing(true);
+        }
+    }
+
+    /// Writes the running game's battery save RAM, if a game is running.
+    pub fn write_sram(&self) {
+        if let Some(emulator) = &self.emulator {
+            if let Err(e) = emulator.write_sram() {
+                log::error!("Couldn't write save RAM: {}", e);
+            }
+        }
+    }
+
+    /// Leaves every screen on top of the menu.
+    pub fn go_to_menu(&mut self) {
+        while self.states.len() > 1 {
+            self.back();
+        }
+    }
+
+    fn show_emulator_error(&mut self, error: String) {
+        log::error!("Emulator error: {}", error);
+
+        let dialog =
+            MessageDialog::new(format!("The game stopped because of an error:\n{}", error))
+                .with_timeout(self.menu.config.menu.message_timeout);
+        self.spawn_urgent_dialog(DynamicDialog::Message(dialog), true);
+    }
+
+    /// Queues a dialog ahead of all non-urgent dialogs. With
+    /// `replace_current`, a non-urgent dialog being shown is put back at the
+    /// front of the queue so the urgent one shows on the next update.
+    pub fn spawn_urgent_dialog(&mut self, dialog: DynamicDialog, replace_current: bool) {
+        self.urgent_dialog_queue.push_back(dialog);
+
+        if replace_current && !self.current_dialog_urgent {
+            if let Some(current) = self.current_dialog.take() {
+                self.dialog_queue.push_front(current);
+            }
+        }
+    }
+
     pub fn render(&mut self) {
-        match self.state {
+        match self.state().clone() {
             AppState::Menu => self.menu.render(),
             AppState::Emulator => {
                 if let Some(emulator) = self.emulator.as_ref() {
@@ -186,14 +441,49 @@ impl App {
 
         // Show dialogs
         if let Some(dialog) = self.current_dialog.as_ref() {
-            match dialog {
-                DynamicDialog::YesOrNo(dialog) => dialog.render(),
-            }
+            dialog.render();
         }
     }
 }
 
-fn draw_loading_screen() {
+/// Scans the library on a separate thread, drawing its progress meanwhile.
+async fn load_game_db(
+    config: Config,
+    cache: Cache,
+    runtime: tokio::runtime::Handle,
+) -> anyhow::Result<(GameDb, Cache)> {
+    let progress = Arc::new(ScanProgress::default());
+    let (result_tx, result_rx) = mpsc::channel();
+
+    thread::spawn({
+        let progress = progress.clone();
+
+        move || {
+            let result = runtime.block_on(GameDb::load_with_subscriber(
+                &cache,
+                &config,
+                &mut LogSubscriber,
+                &progress,
+            ));
+            let _ = result_tx.send(result.map(|game_db| (game_db, cache)));
+        }
+    });
+
+    loop {
+        match result_rx.try_recv() {
+            Ok(result) => return result,
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => anyhow::bail!("the library scan crashed"),
+        }
+
+        draw_loading_screen(&progress);
+        next_frame().await;
+    }
+}
+
+fn draw_loading_screen(progress: &ScanProgress) {
+    clear_background(BLACK);
+
     draw_text(
         "Loading...",
         10.0,
@@ -201,4 +491,25 @@ fn draw_loading_screen() {
         48.0,
         Color::from_rgba(255, 255, 255, 255),
     );
+
+    let total = progress.total.load(Ordering::Relaxed);
+    let hashed = progress.hashed.load(Ordering::Relaxed);
+
+    // The total is only known once the ROM directory has been walked
+    if total > 0 {
+        let width = screen_width() - 20.0;
+        let height = 24.0;
+        let y = screen_height() - 90.0;
+        let fraction = hashed as f32 / total as f32;
+
+        draw_rectangle_lines(10.0, y, width, height, 2.0, LIGHTGRAY);
+        draw_rectangle(10.0, y, width * fraction, height, LIGHTGRAY);
+        draw_text(
+            &format!("Hashing ROMs: {}/{}", hashed, total),
+            10.0,
+            y - 10.0,
+            24.0,
+            LIGHTGRAY,
+        );
+    }
 }
@@ -1,117 +1,465 @@
-use std::{collections::HashMap, io::Write, process::Command};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
 
-use gilrs::{Button, Event, Gilrs};
+use anyhow::Context;
+use gilrs::{Button, Event, EventType, Gilrs};
 use macroquad::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::{cache::Cache, config::Config, game_db::GameDb, AppEvent};
+use crate::{
+    cache::Cache,
+    config::{Combo, Config, Key, MenuView},
+    dat::Verification,
+    dialog::{DynamicDialog, YesOrNoDialog},
+    emulator::GameLaunch,
+    game_db::{Game, GameDb, GameId},
+    sound::{MenuAudio, SoundEffect},
+    watcher::{RomChange, RomWatcher},
+    AppEvent,
+};
 
 pub struct MenuState {
     pub game_db: GameDb,
     pub config: Config,
     pub cache: Cache,
-    pub textures: HashMap<i64, Texture2D>,
+    pub textures: HashMap<GameId, CoverTexture>,
+    /// Logos of the systems that have one, by system ID.
+    pub logos: HashMap<i64, Texture2D>,
+    pub rom_watcher: Option<RomWatcher>,
     pub input: MenuInput,
+    /// Sound effects, if any are configured.
+    pub audio: Option<MenuAudio>,
 
-    pub selected_game: usize,
+    /// Games in the order they are shown. Rebuilt with
+    /// [`MenuState::refresh_order`] whenever the library changes.
+    pub order: Vec<GameId>,
+    /// Position of each game in `order`, so the selection's position is
+    /// found without scanning it.
+    pub order_index: HashMap<GameId, usize>,
+    pub selected: Option<GameId>,
     pub max_tile_size: usize,
+    /// Row drawn at the top of the grid. Follows the scroll row smoothly
+    /// instead of jumping to it.
+    pub scroll_offset: f32,
 
     pub glowing_material: Material,
     pub time: f32,
+    /// Seconds since the selected game changed, for scrolling long titles.
+    pub title_time: f32,
+    /// Seconds since the last input.
+    pub idle_time: f32,
+    /// Whether the poweroff and reboot combos were held last frame, so
+    /// holding one only asks once.
+    pub combos_held: (bool, bool),
 }
 
 impl MenuState {
+    /// Rebuilds the displayed game order, sorted by title. The selection
+    /// stays on the same game, or on the same position if the game is gone.
+    pub fn refresh_order(&mut self) {
+        let previous_index = self.selected_index();
+
+        let game_db = &self.game_db;
+        let mut order: Vec<_> = game_db.games_iter().map(|(id, _)| id).collect();
+        order.sort_by_cached_key(|id| game_db.get_game(*id).title().to_lowercase());
+        self.order_index = order.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        self.order = order;
+
+        if self.selected_game().is_none() {
+            self.select_index(previous_index);
+        }
+    }
+
+    /// Saves `screenshot` as the selected game's custom cover.
+    pub fn set_custom_cover(&mut self, screenshot: &Image) -> anyhow::Result<()> {
+        let id = self.selected_game().context("no game selected")?;
+        let path = custom_cover_path(&self.config, self.game_db.get_game(id));
+
+        fs::create_dir_all(&self.config.cover_path)?;
+        image::save_buffer(
+            &path,
+            &screenshot.bytes,
+            screenshot.width as u32,
+            screenshot.height as u32,
+            image::ColorType::Rgba8,
+        )?;
+        log::info!("Saved cover to {:?}", path);
+
+        // Reload the texture next time it's drawn
+        self.unload_texture(id);
+        Ok(())
+    }
+
+    fn unload_texture(&mut self, id: GameId) {
+        if let Some(CoverTexture {
+            texture: Some(texture),
+            ..
+        }) = self.textures.remove(&id)
+        {
+            texture.delete();
+        }
+    }
+
+    /// Frees the least recently drawn cover textures above the configured
+    /// limit. They're loaded again from the cache if they come back into
+    /// view.
+    fn evict_textures(&mut self) {
+        let max_textures = self.config.menu.max_textures;
+
+        if max_textures == 0 || self.textures.len() <= max_textures {
+            return;
+        }
+
+        let mut by_last_use: Vec<_> = self
+            .textures
+            .iter()
+            .map(|(id, cover)| (cover.last_used, *id))
+            .collect();
+        by_last_use.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let excess = self.textures.len() - max_textures;
+        for (_, id) in by_last_use.into_iter().take(excess) {
+            self.unload_texture(id);
+        }
+    }
+
+    pub fn play_sound(&self, effect: SoundEffect) {
+        if let Some(audio) = &self.audio {
+            audio.play(effect);
+        }
+    }
+
+    pub fn set_music_playing(&self, playing: bool) {
+        if let Some(audio) = &self.audio {
+            audio.set_music_playing(playing);
+        }
+    }
+
+    /// Whether there's been no input for longer than the idle timeout.
+    pub fn is_idle(&self) -> bool {
+        let timeout = self.config.menu.idle_timeout;
+        timeout > 0.0 && self.idle_time > timeout
+    }
+
+    /// The selected game, unless there are no games or it's no longer in
+    /// the order.
+    pub fn selected_game(&self) -> Option<GameId> {
+        self.selected
+            .filter(|selected| self.order_index.contains_key(selected))
+    }
+
+    /// Position of the selected game in the current order.
+    pub fn selected_index(&self) -> usize {
+        self.selected
+            .and_then(|selected| self.order_index.get(&selected).copied())
+            .unwrap_or(0)
+    }
+
+    /// Index of the first game starting with the next (or previous) letter
+    /// after the game at `index`.
+    fn letter_jump(&self, index: usize, forward: bool) -> usize {
+        let letter_at = |i: usize| jump_letter(self.game_db.get_game(self.order[i]).title());
+
+        if self.order.is_empty() {
+            return 0;
+        }
+
+        let index = index.min(self.order.len() - 1);
+        let letter = letter_at(index);
+
+        if forward {
+            (index..self.order.len())
+                .find(|i| letter_at(*i) != letter)
+                .unwrap_or(index)
+        } else {
+            // Start of the current letter, then of the one before it
+            let start = (0..index)
+                .rev()
+                .find(|i| letter_at(*i) != letter)
+                .map_or(0, |i| i + 1);
+            let previous = match start.checked_sub(1) {
+                Some(previous) => letter_at(previous),
+                None => return 0,
+            };
+
+            (0..start)
+                .rev()
+                .find(|i| letter_at(*i) != previous)
+                .map_or(0, |i| i + 1)
+        }
+    }
+
+    /// Width taken by the games, leaving the rest to the detail panel.
+    fn grid_width(&self) -> f32 {
+        if self.config.menu.detail_panel {
+            screen_width() * (1.0 - DETAIL_PANEL_FRACTION)
+        } else {
+            screen_width()
+        }
+    }
+
+    /// Games per row and the height of a row, in the current view.
+    fn row_layout(&self) -> (usize, f32) {
+        match self.config.menu.view {
+            MenuView::Grid => {
+                let row_width = self.grid_width() as usize / self.max_tile_size;
+                (row_width, self.grid_width() / row_width as f32)
+            }
+            MenuView::List => (1, LIST_ROW_HEIGHT),
+        }
+    }
+
+    /// Row the games should be scrolled to, keeping the selection around the
+    /// middle of the screen.
+    fn scroll_row(&self) -> usize {
+        let (row_width, game_size) = self.row_layout();
+        let current_row = self.selected_index() / row_width;
+        let max_rows = (screen_height() - MARGIN) / game_size;
+        // Max rows / 2 because the scrolling needs to happen before
+        current_row.saturating_sub(max_rows as usize / 2)
+    }
+
+    fn select_index(&mut self, index: usize) {
+        let index = index.min(self.order.len().saturating_sub(1));
+        self.selected = self.order.get(index).copied();
+    }
+
     pub fn update(&mut self, gilrs: &mut Gilrs) -> AppEvent {
-        let previous_game = self.selected_game;
-        let game_count = self.game_db.games_iter().count();
-        let row_width = screen_width() as usize / self.max_tile_size;
+        // Merge ROMs added or removed since the last frame
+        let mut library_changed = false;
+
+        if let Some(rom_watcher) = &self.rom_watcher {
+            while let Some(change) = rom_watcher.try_recv() {
+                library_changed = true;
+
+                match change {
+                    RomChange::Added { rom_id, game } => {
+                        log::info!("ROM added: {:?}", game.rom_path);
+                        self.game_db.remove_rom(&game.rom_path);
+                        self.game_db.insert_game(rom_id, game);
+                    }
+                    RomChange::Removed { path } => {
+                        if self.game_db.remove_rom(&path) {
+                            log::info!("ROM removed: {:?}", path);
+                        }
+                    }
+                }
+            }
+        }
+
+        if library_changed {
+            self.refresh_order();
+        }
+
+        let previous_game = self.selected_game();
+        let selected_index = self.selected_index();
 
         self.input = get_input(gilrs, &self.input);
-        self.selected_game = match self.input.direction {
-            InputDirection::Right => self.selected_game.saturating_add(1),
-            InputDirection::Left => self.selected_game.saturating_sub(1),
-            InputDirection::Down => self.selected_game.saturating_add(row_width),
-            InputDirection::Up => self.selected_game.saturating_sub(row_width),
-            InputDirection::None => self.selected_game,
-        };
-        self.selected_game = self.selected_game.max(0).min(game_count.saturating_sub(1));
 
-        // Glow effect reset
-        if self.selected_game != previous_game {
+        if self.input.toggle_view {
+            self.config.menu.view = match self.config.menu.view {
+                MenuView::Grid => MenuView::List,
+                MenuView::List => MenuView::Grid,
+            };
+            // Rows are a different size now, so don't glide from the old one
+            self.scroll_offset = self.scroll_row() as f32;
+            save_view(&self.config);
+        }
+
+        let (row_width, _) = self.row_layout();
+
+        if self.input != MenuInput::default() || get_last_key_pressed().is_some() {
+            self.idle_time = 0.0;
+        } else {
+            self.idle_time += get_frame_time();
+        }
+
+        self.select_index(match self.input.direction {
+            InputDirection::Down if self.input.jump => self.letter_jump(selected_index, true),
+            InputDirection::Up if self.input.jump => self.letter_jump(selected_index, false),
+            InputDirection::Right => selected_index.saturating_add(1),
+            InputDirection::Left => selected_index.saturating_sub(1),
+            InputDirection::Down => selected_index.saturating_add(row_width),
+            InputDirection::Up => selected_index.saturating_sub(row_width),
+            InputDirection::None => selected_index,
+        });
+
+        // Glow effect and title scrolling reset
+        if self.selected_game() != previous_game {
             self.time = 0.0;
+            self.title_time = 0.0;
+            self.play_sound(SoundEffect::Navigate);
         }
 
         // Check for poweroff/reboot commands
         #[cfg(target_os = "linux")]
-        poweroff_reboot_check(gilrs, &self.config);
+        if let Some(event) = poweroff_reboot_check(gilrs, &self.config, &mut self.combos_held) {
+            return event;
+        }
 
-        if self.input.enter {
-            let (_id, game) = &self.game_db.games_iter().nth(self.selected_game).unwrap();
+        if let (true, Some(id)) = (self.input.enter, self.selected_game()) {
+            let game = self.game_db.get_game(id);
             let system = &self.game_db.get_system(game.system_id);
 
             let rom = game.rom_path.clone();
             let core = system.core_path.clone();
+            let sram = self.config.save_path.join(format!("{}.srm", game.hash));
+            let rotation = self
+                .config
+                .game_rotation
+                .get(&game.hash)
+                .copied()
+                .unwrap_or(system.rotation);
 
-            AppEvent::StartEmulator {
+            AppEvent::StartEmulator(GameLaunch {
                 core,
+                core_name: system.core_name.clone(),
                 rom,
                 save: None,
-            }
+                sram,
+                rom_hash: game.hash.clone(),
+                rotation,
+            })
         } else {
             AppEvent::Continue
         }
     }
 
+    /// Draws the game's cover and details right of the games, between `top`
+    /// and `bottom`.
+    fn render_detail_panel(&self, id: GameId, top: f32, bottom: f32) {
+        const TEXT_SIZE: f32 = 26.0;
+        const PADDING: f32 = 20.0;
+
+        let game = self.game_db.get_game(id);
+        let system = self.game_db.get_system(game.system_id);
+        let x = self.grid_width();
+        let width = screen_width() - x;
+
+        draw_rectangle(
+            x,
+            top,
+            width,
+            bottom - top,
+            Color::from_rgba(50, 50, 50, 255),
+        );
+
+        let cover_size = (width - 2.0 * PADDING).min((bottom - top) / 2.0);
+        let cover_x = x + (width - cover_size) / 2.0;
+        let mut y = top + PADDING;
+
+        match self.textures.get(&id).and_then(|cover| cover.texture) {
+            Some(texture) => draw_texture_ex(
+                texture,
+                cover_x,
+                y,
+                WHITE,
+                DrawTextureParams {
+                    dest_size: Some(Vec2::new(cover_size, cover_size)),
+                    ..Default::default()
+                },
+            ),
+            None => draw_rectangle(cover_x, y, cover_size, cover_size, game.color),
+        }
+        y += cover_size + PADDING;
+
+        let title = wrap_text(game.title(), width - 2.0 * PADDING, TEXT_SIZE)
+            .into_iter()
+            .map(|line| (line, WHITE));
+
+        let metadata = game.metadata.as_ref();
+        let details = [
+            Some(system.display_name().to_string()),
+            metadata
+                .and_then(|metadata| metadata.region.as_ref())
+                .map(|region| format!("Region: {}", region)),
+            match game.verification {
+                Verification::Verified => Some("Verified dump".to_string()),
+                Verification::BadDump => Some("Bad dump".to_string()),
+                Verification::Unknown => None,
+            },
+            Some(game.filename.clone()),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|line| (line, LIGHTGRAY));
+
+        for (line, color) in title.chain(details) {
+            y += TEXT_SIZE;
+
+            if y > bottom {
+                break;
+            }
+
+            draw_text(&line, x + PADDING, y, TEXT_SIZE, color);
+        }
+    }
+
     pub fn render(&mut self) {
         clear_background(DARKGRAY);
 
-        let row_width = screen_width() as usize / self.max_tile_size;
-        let game_size = (screen_width() / row_width as f32) as f32;
-        let current_row = self.selected_game / row_width;
-        let max_rows = (screen_height() - MARGIN) / game_size;
-        // Max rows / 2 because the scrolling needs to happen before
-        let scroll = (current_row as usize).saturating_sub(max_rows as usize / 2);
+        let (row_width, game_size) = self.row_layout();
+        let scroll = self.scroll_row();
 
-        for (gfx_counter, (counter, (_id, game))) in self
-            .game_db
-            .games_iter()
-            .enumerate()
-            .skip(scroll * row_width)
+        // Glide towards the scroll row, settling within a few frames
+        let target = scroll as f32;
+        self.scroll_offset +=
+            (target - self.scroll_offset) * (1.0 - (-SCROLL_SPEED * get_frame_time()).exp());
+        if (target - self.scroll_offset).abs() < 0.01 {
+            self.scroll_offset = target;
+        }
+
+        let first_row = self.scroll_offset.floor();
+        let row_offset = self.scroll_offset - first_row;
+
+        for (gfx_counter, id) in self
+            .order
+            .iter()
+            .skip(first_row as usize * row_width)
             .enumerate()
         {
+            let game = self.game_db.get_game(*id);
+            let selected = self.selected == Some(*id);
+            let glowing = selected && self.config.menu.glow.enabled;
             let x = (gfx_counter % row_width) as f32 * game_size;
-            let y = (gfx_counter / row_width) as f32 * game_size + TITLE_TEXT_SIZE + MARGIN;
+            let row = (gfx_counter / row_width) as f32 - row_offset;
+            let y = row * game_size + TITLE_TEXT_SIZE + MARGIN;
+
+            // Games below the bottom of the screen aren't visible
+            if y > screen_height() {
+                break;
+            }
+
+            if glowing {
+                // Freeze the glow while idle, leaving the cover unzoomed
+                if self.is_idle() {
+                    self.time = 0.0;
+                } else {
+                    self.time += get_frame_time();
+                }
 
-            if counter == self.selected_game {
-                self.time += get_frame_time();
                 self.glowing_material.set_uniform("time", self.time);
                 gl_use_material(self.glowing_material);
             }
 
-            if let Some(metadata) = &game.metadata {
-                let cover_url = &metadata.cover_url;
-
-                let texture = self.textures.entry(metadata.release_id).or_insert_with(|| {
-                    if let Ok(bytes) = self.cache.get_or_insert_image(cover_url, |url| {
-                        Ok(reqwest::blocking::get(url)?.bytes()?.to_vec())
-                    }) {
-                        let image = image::load_from_memory(&bytes[..]).unwrap();
-                        let rgba8 = image.to_rgba8();
-                        let bytes: Vec<_> = rgba8.as_raw().as_slice().to_vec();
-
-                        let img = Image {
-                            bytes,
-                            width: rgba8.width() as u16,
-                            height: rgba8.height() as u16,
-                        };
-
-                        Texture2D::from_image(&img)
-                    } else {
-                        Texture2D::from_rgba8(8, 8, &[255u8; 8 * 8])
-                    }
-                });
+            let cover = self.textures.entry(*id).or_insert_with(|| CoverTexture {
+                texture: load_cover_texture(&self.cache, &self.config, game),
+                last_used: 0.0,
+            });
+            cover.last_used = get_time();
 
+            if let Some(texture) = cover.texture {
                 draw_texture_ex(
-                    *texture,
+                    texture,
                     x,
                     y,
                     Color::new(1.0, 1.0, 1.0, 1.0),
@@ -130,19 +478,95 @@ impl MenuState {
                 draw_rectangle(x, y, game_size, game_size, game.color);
             }
 
-            if counter == self.selected_game {
+            if glowing {
                 gl_use_default_material();
-                draw_rectangle_lines(x, y, game_size, game_size, 8.0, BLACK);
             }
+
+            // Tell regional versions of the same game apart
+            let region = game
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.region.as_deref());
+            if let Some(region) = region {
+                draw_region_badge(region, x + game_size, y);
+            }
+
+            if self.config.menu.view == MenuView::List {
+                let system = self.game_db.get_system(game.system_id);
+                let text_y = y + (game_size + LIST_TEXT_SIZE) / 2.0 - 4.0;
+                let system_size =
+                    measure_text(system.display_name(), None, LIST_TEXT_SIZE as u16, 1.0);
+
+                draw_text(
+                    game.title(),
+                    game_size + 20.0,
+                    text_y,
+                    LIST_TEXT_SIZE,
+                    if selected { WHITE } else { LIGHTGRAY },
+                );
+                draw_text(
+                    system.display_name(),
+                    self.grid_width() - system_size.width - 20.0,
+                    text_y,
+                    LIST_TEXT_SIZE,
+                    GRAY,
+                );
+            }
+
+            if selected {
+                let width = match self.config.menu.view {
+                    MenuView::Grid => game_size,
+                    MenuView::List => self.grid_width(),
+                };
+                draw_rectangle_lines(x, y, width, game_size, 8.0, BLACK);
+            }
+        }
+
+        // Covers a row past either edge of the screen are loaded ahead of
+        // time, one per frame, so scrolling doesn't stall on a whole row
+        let visible_rows =
+            ((screen_height() - TITLE_TEXT_SIZE - MARGIN) / game_size).ceil() as usize;
+        let first = scroll.saturating_sub(LOOKAHEAD_ROWS) * row_width;
+        let last = (scroll + visible_rows + LOOKAHEAD_ROWS) * row_width;
+        let next = self
+            .order
+            .iter()
+            .take(last)
+            .skip(first)
+            .find(|id| !self.textures.contains_key(id))
+            .copied();
+
+        if let Some(id) = next {
+            let game = self.game_db.get_game(id);
+            let cover = CoverTexture {
+                texture: load_cover_texture(&self.cache, &self.config, game),
+                last_used: get_time(),
+            };
+            self.textures.insert(id, cover);
         }
 
-        const MARGIN: f32 = 10.0;
+        self.evict_textures();
+
         const TITLE_TEXT_SIZE: f32 = 30.0;
+        const LOOKAHEAD_ROWS: usize = 1;
+        const CORE_INFO_TEXT_SIZE: f32 = 20.0;
+        const LOGO_HEIGHT: f32 = 24.0;
+        const SCROLL_SPEED: f32 = 15.0;
+        const LIST_TEXT_SIZE: f32 = 28.0;
 
-        if let Some((_id, game)) = self.game_db.games_iter().nth(self.selected_game) {
+        if let Some(id) = self.selected_game() {
+            let game = self.game_db.get_game(id);
             let system = &self.game_db.get_system(game.system_id);
 
-            // Show console name
+            if self.config.menu.detail_panel {
+                self.render_detail_panel(
+                    id,
+                    TITLE_TEXT_SIZE + MARGIN,
+                    screen_height() - MARGIN - 24.0,
+                );
+            }
+
+            // Show console logo, or its name if it has none
             draw_rectangle(
                 0.0,
                 screen_height() - MARGIN - 24.0,
@@ -150,29 +574,371 @@ impl MenuState {
                 MARGIN + 24.0,
                 DARKGRAY,
             );
+
+            if let Some(logo) = self.logos.get(&system.id) {
+                let width = logo.width() * LOGO_HEIGHT / logo.height();
+
+                draw_texture_ex(
+                    *logo,
+                    20.0,
+                    screen_height() - MARGIN / 2.0 - LOGO_HEIGHT,
+                    WHITE,
+                    DrawTextureParams {
+                        dest_size: Some(Vec2::new(width, LOGO_HEIGHT)),
+                        ..Default::default()
+                    },
+                );
+            } else {
+                draw_text(
+                    system.display_name(),
+                    20.0,
+                    screen_height() - MARGIN,
+                    TITLE_TEXT_SIZE,
+                    LIGHTGRAY,
+                );
+            }
+
+            // Show the core running the system, right-aligned
+            let core_info = format!("{} {}", system.core_name, system.core_version);
+            let core_info_size = measure_text(&core_info, None, CORE_INFO_TEXT_SIZE as u16, 1.0);
             draw_text(
-                &system.name,
-                20.0,
+                &core_info,
+                screen_width() - core_info_size.width - 20.0,
                 screen_height() - MARGIN,
+                CORE_INFO_TEXT_SIZE,
+                GRAY,
+            );
+
+            // The dump status goes next to the title, if a DAT knows the ROM
+            let verification = match game.verification {
+                Verification::Verified => Some(("Verified", GREEN)),
+                Verification::BadDump => Some(("Bad dump", RED)),
+                Verification::Unknown => None,
+            };
+            let verification_width = verification.map_or(0.0, |(text, _)| {
+                measure_text(text, None, CORE_INFO_TEXT_SIZE as u16, 1.0).width + 20.0
+            });
+
+            // Rows gliding past the top go under the title
+            draw_rectangle(0.0, 0.0, screen_width(), TITLE_TEXT_SIZE + MARGIN, DARKGRAY);
+
+            // Show game title, scrolling back and forth if it doesn't fit
+            let title_width = measure_text(game.title(), None, TITLE_TEXT_SIZE as u16, 1.0).width;
+            let available_width = screen_width() - 40.0 - verification_width;
+            self.title_time += get_frame_time();
+
+            draw_text(
+                game.title(),
+                20.0 - title_scroll(title_width - available_width, self.title_time),
+                TITLE_TEXT_SIZE,
                 TITLE_TEXT_SIZE,
                 LIGHTGRAY,
             );
 
-            let text = if let Some(metadata) = &game.metadata {
-                metadata.title.as_str()
-            } else {
-                game.filename.as_str()
-            };
-            // Show game title
-            draw_text(text, 20.0, TITLE_TEXT_SIZE, TITLE_TEXT_SIZE, LIGHTGRAY);
+            if let Some((text, color)) = verification {
+                let x = 40.0 + title_width.min(available_width);
+
+                draw_rectangle(
+                    x - 20.0,
+                    0.0,
+                    screen_width(),
+                    TITLE_TEXT_SIZE + MARGIN,
+                    DARKGRAY,
+                );
+                draw_text(text, x, TITLE_TEXT_SIZE, CORE_INFO_TEXT_SIZE, color);
+            }
+        }
+    }
+}
+
+/// Space around the edges of the menu.
+const MARGIN: f32 = 10.0;
+
+/// Share of the screen's width taken by the detail panel.
+const DETAIL_PANEL_FRACTION: f32 = 0.35;
+
+/// Splits `text` into lines no wider than `max_width` at `font_size`,
+/// breaking between words.
+fn wrap_text(text: &str, max_width: f32, font_size: f32) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for word in text.split_whitespace() {
+        let candidate = match lines.last() {
+            Some(line) => format!("{} {}", line, word),
+            None => word.to_string(),
+        };
+
+        let fits = measure_text(&candidate, None, font_size as u16, 1.0).width <= max_width;
+        match lines.last_mut() {
+            Some(line) if fits => *line = candidate,
+            _ => lines.push(word.to_string()),
         }
     }
+
+    lines
+}
+
+/// The view last switched to, remembered between runs. It's kept in its own
+/// file because rewriting retroarcade.toml would lose its comments.
+#[derive(Serialize, Deserialize)]
+struct SavedView {
+    view: MenuView,
+}
+
+fn saved_view_path(config: &Config) -> PathBuf {
+    config.cache_path.join("menu_view.toml")
+}
+
+/// The view last switched to, if it was ever switched.
+pub fn load_saved_view(config: &Config) -> Option<MenuView> {
+    let contents = fs::read_to_string(saved_view_path(config)).ok()?;
+
+    match toml::from_str::<SavedView>(&contents) {
+        Ok(saved) => Some(saved.view),
+        Err(e) => {
+            log::error!("Couldn't read saved menu view: {}", e);
+            None
+        }
+    }
+}
+
+fn save_view(config: &Config) {
+    let saved = SavedView {
+        view: config.menu.view,
+    };
+    let result = toml::to_string(&saved)
+        .map_err(anyhow::Error::from)
+        .and_then(|contents| Ok(fs::write(saved_view_path(config), contents)?));
+
+    if let Err(e) = result {
+        log::error!("Couldn't save menu view: {}", e);
+    }
+}
+
+/// Height of a game's row in the list view.
+const LIST_ROW_HEIGHT: f32 = 56.0;
+
+/// Draws a short code for an OpenVGDB region (`USA, Europe` becomes
+/// `US/EU`) in a box whose top-right corner is at `right`, `top`.
+fn draw_region_badge(region: &str, right: f32, top: f32) {
+    const TEXT_SIZE: f32 = 18.0;
+    const PADDING: f32 = 4.0;
+
+    let code = region
+        .split(',')
+        .map(|region| match region.trim() {
+            "USA" => "US".to_string(),
+            "Europe" => "EU".to_string(),
+            "Japan" => "JP".to_string(),
+            "World" => "W".to_string(),
+            "Korea" => "KR".to_string(),
+            "Brazil" => "BR".to_string(),
+            other => other.chars().take(2).collect::<String>().to_uppercase(),
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let size = measure_text(&code, None, TEXT_SIZE as u16, 1.0);
+    let width = size.width + 2.0 * PADDING;
+    let height = TEXT_SIZE + PADDING;
+
+    draw_rectangle(
+        right - width,
+        top,
+        width,
+        height,
+        Color::new(0.0, 0.0, 0.0, 0.7),
+    );
+    draw_text(
+        &code,
+        right - width + PADDING,
+        top + TEXT_SIZE - 2.0,
+        TEXT_SIZE,
+        WHITE,
+    );
+}
+
+/// Letter a title is grouped under when jumping through the list. Titles
+/// not starting with a letter all fall under `#`.
+fn jump_letter(title: &str) -> char {
+    match title.chars().next() {
+        Some(c) if c.is_alphabetic() => c.to_lowercase().next().unwrap_or(c),
+        _ => '#',
+    }
+}
+
+/// Horizontal offset of a title that's `overflow` pixels too wide, `time`
+/// seconds after it was selected. It pauses at either end before scrolling
+/// the other way.
+fn title_scroll(overflow: f32, time: f32) -> f32 {
+    const SPEED: f32 = 60.0;
+    const PAUSE: f32 = 1.5;
+
+    if overflow <= 0.0 {
+        return 0.0;
+    }
+
+    let half_cycle = overflow / SPEED + 2.0 * PAUSE;
+    let t = time % (2.0 * half_cycle);
+    let t = if t < half_cycle {
+        t
+    } else {
+        2.0 * half_cycle - t
+    };
+
+    ((t - PAUSE) * SPEED).clamp(0.0, overflow)
+}
+
+/// Extensions of cover images looked for next to ROMs.
+const COVER_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+/// Decodes the game's cover into a texture. Covers that fail to load show as
+/// a white square.
+fn load_cover_texture(cache: &Cache, config: &Config, game: &Game) -> Option<Texture2D> {
+    let texture = load_cover(cache, config, game)?
+        .and_then(|bytes| Ok(decode_texture(&bytes)?))
+        .unwrap_or_else(|e| {
+            log::error!("Couldn't load cover of {:?}: {:#}", game.rom_path, e);
+            Texture2D::from_rgba8(8, 8, &[255u8; 8 * 8])
+        });
+
+    Some(texture)
+}
+
+/// Loads the logo of every system that has one configured. Systems whose
+/// logo can't be read fall back to showing their name.
+pub fn load_logos(game_db: &GameDb) -> HashMap<i64, Texture2D> {
+    game_db
+        .systems()
+        .values()
+        .filter_map(|system| {
+            let path = system.logo.as_ref()?;
+            let texture = fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Ok(decode_texture(&bytes)?));
+
+            match texture {
+                Ok(texture) => Some((system.id, texture)),
+                Err(e) => {
+                    log::error!("Couldn't load logo {:?}: {}", path, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn decode_texture(bytes: &[u8]) -> image::ImageResult<Texture2D> {
+    let rgba8 = image::load_from_memory(bytes)?.to_rgba8();
+
+    let img = Image {
+        width: rgba8.width() as u16,
+        height: rgba8.height() as u16,
+        bytes: rgba8.into_raw(),
+    };
+
+    Ok(Texture2D::from_image(&img))
+}
+
+fn custom_cover_path(config: &Config, game: &Game) -> PathBuf {
+    config.cover_path.join(format!("{}.png", game.hash))
+}
+
+/// Reads the game's cover image, preferring a custom cover, then an image
+/// next to the ROM with the same name, then the metadata's local file, then
+/// downloading it. `None` if the game has no cover at all.
+fn load_cover(cache: &Cache, config: &Config, game: &Game) -> Option<anyhow::Result<Vec<u8>>> {
+    let custom_cover = custom_cover_path(config, game);
+    let sidecar_cover = COVER_EXTENSIONS
+        .iter()
+        .map(|extension| game.rom_path.with_extension(extension))
+        .find(|path| path.is_file());
+
+    if let Some(cover) = Some(custom_cover)
+        .filter(|path| path.is_file())
+        .or(sidecar_cover)
+    {
+        return Some(fs::read(cover).map_err(Into::into));
+    }
+
+    let metadata = game.metadata.as_ref()?;
+
+    if let Some(cover_path) = &metadata.cover_path {
+        Some(fs::read(cover_path).map_err(Into::into))
+    } else {
+        let cover_url = metadata.cover_url.as_ref()?;
+        Some(fetch_cover(cache, config, cover_url))
+    }
+}
+
+/// Gets a scraped cover from the cache, downloading it if it's missing or
+/// too old.
+fn fetch_cover(cache: &Cache, config: &Config, url: &str) -> anyhow::Result<Vec<u8>> {
+    let max_age = Some(config.cover_max_age_days)
+        .filter(|days| *days > 0)
+        .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+
+    cache.get_or_insert_image(url, max_age, |url| {
+        Ok(reqwest::blocking::get(url)?.bytes()?.to_vec())
+    })
+}
+
+/// Downloads every scraped cover in the library into the cache in the
+/// background, a few at a time, so they're ready before they're scrolled to.
+/// Covers already cached aren't downloaded again.
+pub fn spawn_cover_prefetch(game_db: &GameDb, cache: Cache, config: Config) {
+    const THREADS: usize = 4;
+
+    let urls: Vec<String> = game_db
+        .games_iter()
+        .filter_map(|(_, game)| game.metadata.as_ref())
+        .filter(|metadata| metadata.cover_path.is_none())
+        .filter_map(|metadata| metadata.cover_url.clone())
+        .collect();
+
+    thread::spawn(move || {
+        let pool = match rayon::ThreadPoolBuilder::new().num_threads(THREADS).build() {
+            Ok(pool) => pool,
+            Err(e) => {
+                log::error!("Couldn't start cover prefetch: {}", e);
+                return;
+            }
+        };
+
+        log::info!("Prefetching {} covers", urls.len());
+        let done = AtomicUsize::new(0);
+
+        pool.install(|| {
+            urls.par_iter().for_each(|url| {
+                if let Err(e) = fetch_cover(&cache, &config, url) {
+                    log::warn!("Couldn't prefetch cover {}: {:#}", url, e);
+                }
+
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                if done % 50 == 0 || done == urls.len() {
+                    log::info!("Prefetched {}/{} covers", done, urls.len());
+                }
+            });
+        });
+    });
+}
+
+pub struct CoverTexture {
+    /// `None` for games without a cover.
+    pub texture: Option<Texture2D>,
+    /// When the cover was last drawn, from [`get_time`].
+    pub last_used: f64,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
 pub struct MenuInput {
     direction: InputDirection,
     enter: bool,
+    /// A shoulder button (or Shift) is held, so up/down jump by letter.
+    jump: bool,
+    /// The view button was just pressed.
+    toggle_view: bool,
+    view_button: bool,
     up: bool,
     down: bool,
     left: bool,
@@ -196,25 +962,40 @@ fn get_input(gilrs: &mut Gilrs, input: &MenuInput) -> MenuInput {
     let mut down = is_key_pressed(KeyCode::Down);
     let mut up = is_key_pressed(KeyCode::Up);
     let mut enter = is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::Space);
+    let mut jump = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+    let mut view_button = is_key_down(KeyCode::Tab);
 
-    // Gamepad input
-    while let Some(Event { .. }) = gilrs.next_event() {}
+    // Gamepad input. Presses are taken from the events too, since a tap
+    // can start and end between two frames while the idle menu sleeps.
+    // Launching only takes a press seen here rather than a held button, so
+    // the press confirming a dialog doesn't launch a game after.
+    let mut pressed = Vec::new();
+    while let Some(Event { event, .. }) = gilrs.next_event() {
+        if let EventType::ButtonPressed(button, _) = event {
+            pressed.push(button);
+        }
+    }
+    let was_pressed = |button| pressed.contains(&button);
+    enter = enter || was_pressed(Button::South) || was_pressed(Button::East);
 
     for (_g_id, gamepad) in gilrs.gamepads() {
         right = right || gamepad.is_pressed(Button::DPadRight);
         left = left || gamepad.is_pressed(Button::DPadLeft);
         down = down || gamepad.is_pressed(Button::DPadDown);
         up = up || gamepad.is_pressed(Button::DPadUp);
-        enter = enter || gamepad.is_pressed(Button::South) || gamepad.is_pressed(Button::East);
+        jump = jump
+            || gamepad.is_pressed(Button::LeftTrigger)
+            || gamepad.is_pressed(Button::RightTrigger);
+        view_button = view_button || gamepad.is_pressed(Button::West);
     }
 
-    let direction = if !input.right && right {
+    let direction = if (!input.right && right) || was_pressed(Button::DPadRight) {
         InputDirection::Right
-    } else if !input.left && left {
+    } else if (!input.left && left) || was_pressed(Button::DPadLeft) {
         InputDirection::Left
-    } else if !input.down && down {
+    } else if (!input.down && down) || was_pressed(Button::DPadDown) {
         InputDirection::Down
-    } else if !input.up && up {
+    } else if (!input.up && up) || was_pressed(Button::DPadUp) {
         InputDirection::Up
     } else {
         InputDirection::None
@@ -223,6 +1004,9 @@ fn get_input(gilrs: &mut Gilrs, input: &MenuInput) -> MenuInput {
     MenuInput {
         direction,
         enter,
+        jump,
+        toggle_view: (!input.view_button && view_button) || was_pressed(Button::West),
+        view_button,
         up,
         down,
         left,
@@ -230,49 +1014,105 @@ fn get_input(gilrs: &mut Gilrs, input: &MenuInput) -> MenuInput {
     }
 }
 
-fn poweroff_reboot_check(gilrs: &Gilrs, config: &Config) {
-    // Check for poweroff/reboot gamepad combinations
-    let (mut poweroff, mut reboot) =
-        gilrs
-            .gamepads()
-            .fold((false, false), |(poweroff, reboot), (_, g)| {
-                let base = g.is_pressed(Button::Select) && g.is_pressed(Button::Start);
-                // Start+Select+L1 = Power off
-                let poweroff = poweroff || (base && g.is_pressed(Button::LeftTrigger));
-                // Start+Select+R1 = Reboot
-                let reboot = reboot || (base && g.is_pressed(Button::RightTrigger));
-                (poweroff, reboot)
-            });
-
-    // Also check for poweroff/reboot key combinations
-    // Ctrl+Alt+End = Power off
-    poweroff = poweroff
-        || (is_key_down(KeyCode::LeftControl)
-            && is_key_down(KeyCode::LeftAlt)
-            && is_key_down(KeyCode::End));
-
-    // Ctrl+Alt+Del = Reboot
-    reboot = reboot
-        || (is_key_down(KeyCode::LeftControl)
-            && is_key_down(KeyCode::LeftAlt)
-            && is_key_down(KeyCode::Delete));
-
-    let exec = |cmd| {
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .output()
-            .expect("failed to execute reboot process");
-        std::io::stdout().write_all(&output.stdout).unwrap();
-        std::io::stderr().write_all(&output.stderr).unwrap();
-    };
+/// Asks for confirmation before running the poweroff or reboot command, since
+/// the combos are easy to hit by accident. Only asks on the frame a combo
+/// becomes held, so answering while still holding it doesn't ask again.
+fn poweroff_reboot_check(
+    gilrs: &Gilrs,
+    config: &Config,
+    held: &mut (bool, bool),
+) -> Option<AppEvent> {
+    let previous = *held;
+    *held = (
+        combo_held(gilrs, &config.menu.poweroff_combo),
+        combo_held(gilrs, &config.menu.reboot_combo),
+    );
 
-    if poweroff {
+    let (question, cmd) = if held.0 && !previous.0 {
         println!("Poweroff requested");
-        exec(&config.menu.poweroff_cmd);
-    }
-    if reboot {
+        ("Power off the system?", config.menu.poweroff_cmd.clone())
+    } else if held.1 && !previous.1 {
         println!("Reboot requested");
-        exec(&config.menu.reboot_cmd);
+        ("Reboot the system?", config.menu.reboot_cmd.clone())
+    } else {
+        return None;
+    };
+
+    let dialog = YesOrNoDialog {
+        text: question.to_string(),
+        value: false,
+        event_handler: Box::new(move |confirmed| {
+            if confirmed {
+                AppEvent::RunCommand(cmd)
+            } else {
+                AppEvent::Continue
+            }
+        }),
+    };
+
+    Some(AppEvent::SpawnDialog(DynamicDialog::YesOrNo(dialog)))
+}
+
+pub fn run_command(cmd: &str) {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .output()
+        .expect("failed to execute reboot process");
+    std::io::stdout().write_all(&output.stdout).unwrap();
+    std::io::stderr().write_all(&output.stderr).unwrap();
+}
+
+/// Whether every button of the combo is held on some gamepad, or every key
+/// on the keyboard.
+fn combo_held(gilrs: &Gilrs, combo: &Combo) -> bool {
+    let on_gamepad = !combo.buttons.is_empty()
+        && gilrs.gamepads().any(|(_, gamepad)| {
+            combo
+                .buttons
+                .iter()
+                .all(|button| gamepad.is_pressed(*button))
+        });
+    let on_keyboard =
+        !combo.keys.is_empty() && combo.keys.iter().all(|key| is_key_down(key_code(*key)));
+
+    on_gamepad || on_keyboard
+}
+
+fn key_code(key: Key) -> KeyCode {
+    match key {
+        Key::LeftControl => KeyCode::LeftControl,
+        Key::RightControl => KeyCode::RightControl,
+        Key::LeftAlt => KeyCode::LeftAlt,
+        Key::RightAlt => KeyCode::RightAlt,
+        Key::LeftShift => KeyCode::LeftShift,
+        Key::RightShift => KeyCode::RightShift,
+        Key::LeftSuper => KeyCode::LeftSuper,
+        Key::RightSuper => KeyCode::RightSuper,
+        Key::Escape => KeyCode::Escape,
+        Key::Tab => KeyCode::Tab,
+        Key::Backspace => KeyCode::Backspace,
+        Key::Enter => KeyCode::Enter,
+        Key::Space => KeyCode::Space,
+        Key::Insert => KeyCode::Insert,
+        Key::Delete => KeyCode::Delete,
+        Key::Home => KeyCode::Home,
+        Key::End => KeyCode::End,
+        Key::PageUp => KeyCode::PageUp,
+        Key::PageDown => KeyCode::PageDown,
+        Key::PrintScreen => KeyCode::PrintScreen,
+        Key::Pause => KeyCode::Pause,
+        Key::F1 => KeyCode::F1,
+        Key::F2 => KeyCode::F2,
+        Key::F3 => KeyCode::F3,
+        Key::F4 => KeyCode::F4,
+        Key::F5 => KeyCode::F5,
+        Key::F6 => KeyCode::F6,
+        Key::F7 => KeyCode::F7,
+        Key::F8 => KeyCode::F8,
+        Key::F9 => KeyCode::F9,
+        Key::F10 => KeyCode::F10,
+        Key::F11 => KeyCode::F11,
+        Key::F12 => KeyCode::F12,
     }
 }
@@ -0,0 +1,252 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use retro_rs::{Buttons, InputPort};
+
+const MAGIC: &[u8; 8] = b"RAMOVIE2";
+/// Bytes taken by one frame: buttons and both stick axes for each port, then
+/// the reset flag.
+const FRAME_SIZE: usize = 2 * 6 + 1;
+
+type SetButton = fn(Buttons, bool) -> Buttons;
+type GetButton = fn(Buttons) -> bool;
+
+/// Order of the buttons in a frame's bitmask.
+const BUTTONS: [(SetButton, GetButton); 16] = [
+    (Buttons::up, Buttons::get_up),
+    (Buttons::down, Buttons::get_down),
+    (Buttons::left, Buttons::get_left),
+    (Buttons::right, Buttons::get_right),
+    (Buttons::a, Buttons::get_a),
+    (Buttons::b, Buttons::get_b),
+    (Buttons::x, Buttons::get_x),
+    (Buttons::y, Buttons::get_y),
+    (Buttons::l1, Buttons::get_l1),
+    (Buttons::r1, Buttons::get_r1),
+    (Buttons::l2, Buttons::get_l2),
+    (Buttons::r2, Buttons::get_r2),
+    (Buttons::l3, Buttons::get_l3),
+    (Buttons::r3, Buttons::get_r3),
+    (Buttons::start, Buttons::get_start),
+    (Buttons::select, Buttons::get_select),
+];
+
+/// A recording of every frame's input, starting from a save state, so a run
+/// can be replayed exactly.
+pub struct Movie {
+    /// Hash of the ROM the movie was recorded with.
+    pub rom_hash: String,
+    /// Save state the recording starts from.
+    pub state: Vec<u8>,
+    frames: Vec<Frame>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Frame {
+    ports: [FrameInput; 2],
+    /// The game was reset right before this frame.
+    reset: bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct FrameInput {
+    buttons: u16,
+    joystick_x: i16,
+    joystick_y: i16,
+}
+
+impl Movie {
+    pub fn new(rom_hash: String, state: Vec<u8>) -> Self {
+        Self {
+            rom_hash,
+            state,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Appends the input of one frame, and whether the game was reset before
+    /// it.
+    pub fn record(&mut self, ports: &[InputPort; 2], reset: bool) {
+        let ports = ports.map(|port| FrameInput {
+            buttons: BUTTONS
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, get))| get(port.buttons))
+                .fold(0, |bits, (i, _)| bits | 1 << i),
+            joystick_x: port.joystick_x,
+            joystick_y: port.joystick_y,
+        });
+
+        self.frames.push(Frame { ports, reset });
+    }
+
+    /// Overwrites the ports with the recorded input of `frame`. Returns
+    /// whether the game has to be reset before the frame, or `None` once the
+    /// recording is over.
+    pub fn play(&self, frame: usize, ports: &mut [InputPort; 2]) -> Option<bool> {
+        let frame = self.frames.get(frame)?;
+
+        for (port, input) in ports.iter_mut().zip(&frame.ports) {
+            port.buttons = BUTTONS
+                .iter()
+                .enumerate()
+                .fold(Buttons::new(), |buttons, (i, (set, _))| {
+                    set(buttons, input.buttons & 1 << i != 0)
+                });
+            port.joystick_x = input.joystick_x;
+            port.joystick_y = input.joystick_y;
+        }
+
+        Some(frame.reset)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("reading movie {:?}", path))?;
+        Self::from_bytes(&bytes).with_context(|| format!("reading movie {:?}", path))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_bytes()).with_context(|| format!("writing movie {:?}", path))
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut rest = bytes.strip_prefix(MAGIC).context("not a movie file")?;
+
+        let rom_hash = String::from_utf8(read_chunk(&mut rest)?.to_vec())?;
+        let state = read_chunk(&mut rest)?.to_vec();
+
+        if rest.len() % FRAME_SIZE != 0 {
+            bail!("movie is truncated");
+        }
+
+        let frames = rest
+            .chunks_exact(FRAME_SIZE)
+            .map(|frame| {
+                let value = |i: usize| [frame[i], frame[i + 1]];
+                let port = |offset: usize| FrameInput {
+                    buttons: u16::from_le_bytes(value(offset)),
+                    joystick_x: i16::from_le_bytes(value(offset + 2)),
+                    joystick_y: i16::from_le_bytes(value(offset + 4)),
+                };
+                Frame {
+                    ports: [port(0), port(6)],
+                    reset: frame[12] != 0,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            rom_hash,
+            state,
+            frames,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        write_chunk(&mut bytes, self.rom_hash.as_bytes());
+        write_chunk(&mut bytes, &self.state);
+
+        for frame in &self.frames {
+            for input in &frame.ports {
+                bytes.extend_from_slice(&input.buttons.to_le_bytes());
+                bytes.extend_from_slice(&input.joystick_x.to_le_bytes());
+                bytes.extend_from_slice(&input.joystick_y.to_le_bytes());
+            }
+            bytes.push(frame.reset as u8);
+        }
+
+        bytes
+    }
+}
+
+/// Reads a length-prefixed chunk, advancing `bytes` past it.
+fn read_chunk<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8]> {
+    if bytes.len() < 4 {
+        bail!("movie is truncated");
+    }
+
+    let (len, rest) = bytes.split_at(4);
+    let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+
+    if rest.len() < len {
+        bail!("movie is truncated");
+    }
+
+    let (chunk, rest) = rest.split_at(len);
+    *bytes = rest;
+    Ok(chunk)
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk: &[u8]) {
+    bytes.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(chunk);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(buttons: Buttons, joystick_x: i16, joystick_y: i16) -> InputPort {
+        let mut port = InputPort::new();
+        port.buttons = buttons;
+        port.joystick_x = joystick_x;
+        port.joystick_y = joystick_y;
+        port
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let mut movie = Movie::new("ABC123".to_string(), vec![1, 2, 3]);
+        movie.record(
+            &[
+                port(Buttons::new().a(true).start(true), i16::MIN, 1234),
+                port(Buttons::new().select(true).r3(true), 0, i16::MAX),
+            ],
+            false,
+        );
+        movie.record(&[InputPort::new(), InputPort::new()], true);
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(movie.rom_hash, "ABC123");
+        assert_eq!(movie.state, [1, 2, 3]);
+        assert_eq!(movie.frame_count(), 2);
+
+        let mut ports = [InputPort::new(), InputPort::new()];
+        assert_eq!(movie.play(0, &mut ports), Some(false));
+        assert!(ports[0].buttons.get_a() && ports[0].buttons.get_start());
+        assert!(!ports[0].buttons.get_b() && !ports[0].buttons.get_select());
+        assert_eq!((ports[0].joystick_x, ports[0].joystick_y), (i16::MIN, 1234));
+        assert!(ports[1].buttons.get_select() && ports[1].buttons.get_r3());
+        assert!(!ports[1].buttons.get_a());
+        assert_eq!((ports[1].joystick_x, ports[1].joystick_y), (0, i16::MAX));
+
+        assert_eq!(movie.play(1, &mut ports), Some(true));
+        assert!(!ports[0].buttons.get_a() && !ports[1].buttons.get_select());
+        assert_eq!(movie.play(2, &mut ports), None);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(Movie::from_bytes(b"RAMOVIE1").is_err());
+        assert!(Movie::from_bytes(b"not a movie").is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_movies() {
+        let mut movie = Movie::new("ABC123".to_string(), vec![0; 16]);
+        movie.record(&[InputPort::new(), InputPort::new()], false);
+        let bytes = movie.to_bytes();
+
+        // Cut into the last frame, then into the save state
+        assert!(Movie::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Movie::from_bytes(&bytes[..MAGIC.len() + 4 + 6 + 4 + 8]).is_err());
+    }
+}
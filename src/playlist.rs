@@ -0,0 +1,87 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// A RetroArch playlist (`.lpl`), in the JSON format used since RetroArch
+/// 1.7.
+#[derive(Deserialize, Debug)]
+struct Playlist {
+    items: Vec<PlaylistItem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlaylistItem {
+    pub path: PathBuf,
+    pub label: String,
+    /// Path to the core RetroArch launches the game with, or `DETECT`.
+    #[serde(default)]
+    pub core_path: String,
+}
+
+pub fn load_playlist(path: &Path) -> Result<Vec<PlaylistItem>> {
+    let contents = fs::read_to_string(path).context("opening playlist")?;
+    let playlist: Playlist = serde_json::from_str(&contents)
+        .context("parsing playlist (only the JSON playlist format is supported)")?;
+
+    Ok(playlist
+        .items
+        .into_iter()
+        .map(|item| PlaylistItem {
+            path: strip_archive_entry(item.path),
+            ..item
+        })
+        .collect())
+}
+
+/// RetroArch points into archives with `archive.zip#game.sfc`. The archive is
+/// what gets scanned and launched, so only its path is kept.
+fn strip_archive_entry(path: PathBuf) -> PathBuf {
+    let archive = path
+        .to_str()
+        .and_then(|path| path.rsplit_once('#'))
+        .map(|(archive, _)| PathBuf::from(archive))
+        .filter(|archive| {
+            archive
+                .extension()
+                .map_or(false, |ext| ext == "zip" || ext == "7z")
+        });
+
+    archive.unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_archive_entries() {
+        assert_eq!(
+            strip_archive_entry(PathBuf::from("/roms/snes/game.zip#game.sfc")),
+            PathBuf::from("/roms/snes/game.zip")
+        );
+        assert_eq!(
+            strip_archive_entry(PathBuf::from("/roms/snes/game.7z#game.sfc")),
+            PathBuf::from("/roms/snes/game.7z")
+        );
+    }
+
+    #[test]
+    fn keeps_paths_without_archives() {
+        let paths = [
+            "/roms/snes/game.sfc",
+            "/roms/snes/Game #1.sfc",
+            "/roms/#1/game.sfc",
+        ];
+
+        for path in paths {
+            assert_eq!(
+                strip_archive_entry(PathBuf::from(path)),
+                PathBuf::from(path)
+            );
+        }
+    }
+}
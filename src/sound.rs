@@ -0,0 +1,207 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use cpal::traits::DeviceTrait;
+
+use crate::{audio, config::MenuSoundConfig};
+
+/// Sound effects played by the menu.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SoundEffect {
+    /// The cursor moved to another game.
+    Navigate,
+    /// A game is being launched.
+    Launch,
+}
+
+/// Seconds the music takes to fade in or out.
+const MUSIC_FADE_TIME: f32 = 1.0;
+
+/// Plays the menu's sounds on their own output stream, mixed with whatever
+/// else is playing.
+pub struct MenuAudio {
+    #[allow(dead_code)]
+    device: cpal::Device,
+    #[allow(dead_code)]
+    stream: cpal::Stream,
+    mixer: Arc<Mutex<Mixer>>,
+    navigate: Option<Arc<[i16]>>,
+    launch: Option<Arc<[i16]>>,
+}
+
+struct Mixer {
+    voices: Vec<Voice>,
+    music: Option<Music>,
+}
+
+/// A sound being played, and how far into it the stream is.
+struct Voice {
+    samples: Arc<[i16]>,
+    position: usize,
+}
+
+/// The looping background track. It keeps its place while faded out, so it
+/// picks up where it left off.
+struct Music {
+    samples: Arc<[i16]>,
+    position: usize,
+    gain: f32,
+    target_gain: f32,
+}
+
+impl Music {
+    /// Adds the music to `output`, moving the gain towards its target by
+    /// `fade_step` every stereo frame.
+    fn mix(&mut self, output: &mut [i16], fade_step: f32) {
+        if self.samples.is_empty() || (self.gain == 0.0 && self.target_gain == 0.0) {
+            return;
+        }
+
+        for frame in output.chunks_exact_mut(2) {
+            self.gain = if self.gain < self.target_gain {
+                (self.gain + fade_step).min(self.target_gain)
+            } else {
+                (self.gain - fade_step).max(self.target_gain)
+            };
+
+            for out in frame {
+                let sample = self.samples[self.position] as f32 * self.gain;
+                *out = out.saturating_add(sample as i16);
+                self.position = (self.position + 1) % self.samples.len();
+            }
+        }
+    }
+}
+
+impl MenuAudio {
+    /// Opens the output stream, loads the configured sounds and starts the
+    /// music. `None` if no sounds are configured, so nothing is opened.
+    pub fn new(config: &MenuSoundConfig) -> Result<Option<Self>> {
+        if config.navigate.is_none() && config.launch.is_none() && config.music.is_none() {
+            return Ok(None);
+        }
+
+        let device = audio::init()?;
+        let sample_rate = device.default_output_config()?.sample_rate().0;
+
+        let load = |path: &Option<_>| -> Result<Option<Arc<[i16]>>> {
+            path.as_deref()
+                .map(|path| load_wav(path, sample_rate, config.volume))
+                .transpose()
+        };
+        let navigate = load(&config.navigate)?;
+        let launch = load(&config.launch)?;
+
+        let music = match &config.music {
+            Some(path) => Some(Music {
+                samples: load_wav(path, sample_rate, config.music_volume)?,
+                position: 0,
+                gain: 0.0,
+                target_gain: 1.0,
+            }),
+            None => None,
+        };
+        let fade_step = 1.0 / (MUSIC_FADE_TIME * sample_rate as f32);
+
+        let mixer = Arc::new(Mutex::new(Mixer {
+            voices: Vec::new(),
+            music,
+        }));
+        let stream = audio::run(&device, {
+            let mixer = mixer.clone();
+
+            move |output| {
+                let mut mixer = mixer.lock().unwrap();
+                output.fill(0);
+
+                if let Some(music) = &mut mixer.music {
+                    music.mix(output, fade_step);
+                }
+
+                let voices = &mut mixer.voices;
+                for voice in voices.iter_mut() {
+                    let rest = &voice.samples[voice.position..];
+                    let len = rest.len().min(output.len());
+
+                    for (out, sample) in output.iter_mut().zip(&rest[..len]) {
+                        *out = out.saturating_add(*sample);
+                    }
+                    voice.position += len;
+                }
+
+                voices.retain(|voice| voice.position < voice.samples.len());
+                true
+            }
+        })?;
+
+        Ok(Some(Self {
+            device,
+            stream,
+            mixer,
+            navigate,
+            launch,
+        }))
+    }
+
+    pub fn play(&self, effect: SoundEffect) {
+        let samples = match effect {
+            SoundEffect::Navigate => &self.navigate,
+            SoundEffect::Launch => &self.launch,
+        };
+
+        if let Some(samples) = samples {
+            self.mixer.lock().unwrap().voices.push(Voice {
+                samples: samples.clone(),
+                position: 0,
+            });
+        }
+    }
+
+    /// Fades the music in or out.
+    pub fn set_music_playing(&self, playing: bool) {
+        if let Some(music) = &mut self.mixer.lock().unwrap().music {
+            music.target_gain = if playing { 1.0 } else { 0.0 };
+        }
+    }
+}
+
+/// Reads a WAV file as interleaved stereo at `sample_rate`, scaled by
+/// `volume`.
+fn load_wav(path: &Path, sample_rate: u32, volume: f32) -> Result<Arc<[i16]>> {
+    let mut reader =
+        hound::WavReader::open(path).with_context(|| format!("opening sound {:?}", path))?;
+    let spec = reader.spec();
+
+    let samples: Vec<i16> = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Int, 16) => reader.samples::<i16>().collect::<Result<_, _>>()?,
+        (hound::SampleFormat::Float, 32) => reader
+            .samples::<f32>()
+            .map(|sample| sample.map(|sample| (sample * i16::MAX as f32) as i16))
+            .collect::<Result<_, _>>()?,
+        (format, bits) => bail!(
+            "sound {:?} is {}-bit {:?}, only 16-bit integer and 32-bit float are supported",
+            path,
+            bits,
+            format
+        ),
+    };
+
+    // Take the first two channels, or the only one twice
+    let channels = spec.channels as usize;
+    let frames: Vec<[i16; 2]> = samples
+        .chunks_exact(channels)
+        .map(|frame| [frame[0], frame[channels.min(2) - 1]])
+        .collect();
+
+    // Nearest-sample resampling is good enough for menu sounds
+    let ratio = spec.sample_rate as f64 / sample_rate as f64;
+    let len = (frames.len() as f64 / ratio) as usize;
+
+    Ok((0..len)
+        .flat_map(|i| frames[((i as f64 * ratio) as usize).min(frames.len() - 1)])
+        .map(|sample| (sample as f32 * volume) as i16)
+        .collect())
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use notify::{event::ModifyKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::runtime::Handle;
+
+use crate::{
+    cache::Cache,
+    config::Config,
+    game_db::{
+        connect_openvgdb, forget_rom_hash, identify_rom, remove_cue_tracks, Game, LogSubscriber,
+        System,
+    },
+    gamelist::GamelistIndex,
+};
+
+/// How long a path must go without filesystem events before it's considered
+/// settled. Files copied over the network trigger lots of writes, and hashing
+/// a half-written ROM would cache a wrong hash.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// `Removed` paths may be directories, taking every ROM inside with them.
+pub enum RomChange {
+    Added { rom_id: Option<i64>, game: Game },
+    Removed { path: PathBuf },
+}
+
+/// A path with recent filesystem events.
+struct PendingPath {
+    last_event: Instant,
+    /// Created or moved into place, rather than just modified.
+    created: bool,
+    /// Its contents may have changed.
+    changed: bool,
+}
+
+/// Watches the ROM directory and identifies new ROMs on a background thread.
+pub struct RomWatcher {
+    _watcher: RecommendedWatcher,
+    changes: mpsc::Receiver<RomChange>,
+}
+
+impl RomWatcher {
+    pub fn spawn(
+        config: &Config,
+        systems: HashMap<i64, System>,
+        cache: Cache,
+        runtime: Handle,
+    ) -> Result<Self> {
+        let (fs_tx, fs_rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(fs_tx)?;
+        for rom_dir in &config.rom_path {
+            watcher.watch(rom_dir, RecursiveMode::Recursive)?;
+        }
+
+        let (change_tx, changes) = mpsc::channel();
+        let openvgdb_path = config.openvgdb_path.clone();
+        let rom_dirs = config.rom_path.clone();
+        let follow_symlinks = config.follow_symlinks;
+
+        thread::spawn(move || {
+            runtime.block_on(async move {
+                let openvgdb = match connect_openvgdb(&openvgdb_path).await {
+                    Ok(openvgdb) => openvgdb,
+                    Err(e) => {
+                        log::error!("ROM watcher couldn't open OpenVGDB: {}", e);
+                        return;
+                    }
+                };
+                let mut conn = match openvgdb.acquire().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        log::error!("ROM watcher couldn't open OpenVGDB: {}", e);
+                        return;
+                    }
+                };
+
+                let mut pending: HashMap<PathBuf, PendingPath> = HashMap::new();
+
+                loop {
+                    match fs_rx.recv_timeout(SETTLE_TIME) {
+                        Ok(Ok(event)) => {
+                            let created = matches!(
+                                event.kind,
+                                EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+                            );
+                            let changed =
+                                matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_));
+
+                            for path in event.paths {
+                                let entry = pending.entry(path).or_insert(PendingPath {
+                                    last_event: Instant::now(),
+                                    created: false,
+                                    changed: false,
+                                });
+                                entry.last_event = Instant::now();
+                                entry.created |= created;
+                                entry.changed |= changed;
+                            }
+                        }
+                        Ok(Err(e)) => log::error!("ROM watcher error: {}", e),
+                        Err(RecvTimeoutError::Timeout) => (),
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+
+                    let settled: Vec<_> = pending
+                        .iter()
+                        .filter(|(_, pending)| pending.last_event.elapsed() >= SETTLE_TIME)
+                        .map(|(path, _)| path.clone())
+                        .collect();
+
+                    // Loaded again each time, in case a gamelist changed
+                    let mut gamelists = GamelistIndex::default();
+
+                    for path in settled {
+                        let PendingPath {
+                            created, changed, ..
+                        } = pending.remove(&path).unwrap();
+
+                        if !path.exists() {
+                            if change_tx.send(RomChange::Removed { path }).is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+
+                        // A directory moved in only gets an event for itself,
+                        // not for the ROMs inside it. Directories that were
+                        // just modified have their own events for any new ROM.
+                        let mut rom_paths: Vec<PathBuf> = if path.is_dir() && !created {
+                            Vec::new()
+                        } else {
+                            walkdir::WalkDir::new(&path)
+                                .follow_links(follow_symlinks)
+                                .into_iter()
+                                .filter_map(|rom| rom.ok())
+                                .filter(|rom| rom.file_type().is_file())
+                                .map(|rom| rom.into_path())
+                                .collect()
+                        };
+                        remove_cue_tracks(&mut rom_paths);
+
+                        for rom_path in rom_paths {
+                            // Hashes are cached by path, so a ROM replaced by
+                            // another file would keep the old one's hash
+                            if changed {
+                                if let Err(e) = forget_rom_hash(&cache, &systems, &rom_path) {
+                                    log::error!(
+                                        "Couldn't clear cached hash of {:?}: {:#}",
+                                        rom_path,
+                                        e
+                                    );
+                                }
+                            }
+
+                            let change = match identify_rom(
+                                &mut conn,
+                                &cache,
+                                &systems,
+                                &mut gamelists,
+                                &rom_dirs,
+                                rom_path,
+                                &mut LogSubscriber,
+                            )
+                            .await
+                            {
+                                Some((rom_id, game)) => RomChange::Added { rom_id, game },
+                                None => continue,
+                            };
+
+                            if change_tx.send(change).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            })
+        });
+
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Returns the next pending change, if any. Never blocks.
+    pub fn try_recv(&self) -> Option<RomChange> {
+        self.changes.try_recv().ok()
+    }
+}